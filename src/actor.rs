@@ -0,0 +1,261 @@
+use std::sync::mpsc::{channel, Sender, Receiver};
+use std::sync::{Arc, Mutex};
+use std::collections::VecDeque;
+use std::panic::{self, AssertUnwindSafe};
+use std::time::{Duration, Instant};
+use future::{Future, SharedFuture, Promise, PanicPayload, panic_message};
+use scope::spawn_async;
+
+pub trait Actor: 'static + Send {
+    type Message: 'static + Send;
+    type Reply: 'static + Send;
+
+    fn handle(&mut self, msg: Self::Message) -> Self::Reply;
+
+    fn started(&mut self) {}
+
+    fn stopped(&mut self) {}
+}
+
+// the promise of an ask; an envelope dropped unhandled, in a stopped actor's mailbox
+// or on the way to it, fails the asker's future instead of leaving it pending
+struct Reply<R: 'static> {
+    promise: Option<Promise<'static, R>>
+}
+
+impl<R: 'static> Reply<R> {
+    fn set(mut self, reply: R) {
+        self.promise.take().unwrap().set(reply);
+    }
+
+    fn fail(mut self, payload: PanicPayload) {
+        self.promise.take().unwrap().set_panic(payload);
+    }
+}
+
+impl<R: 'static> Drop for Reply<R> {
+    fn drop(&mut self) {
+        if let Some(promise) = self.promise.take() {
+            promise.set_panic(Box::new("actor stopped before handling the message"));
+        }
+    }
+}
+
+// the handler's payload is re-raised or reported by the supervisor, the asker gets
+// its message
+fn ask_failure(payload: &PanicPayload) -> PanicPayload {
+    Box::new(panic_message(payload).unwrap_or("actor panicked").to_string())
+}
+
+enum Envelope<A: Actor> {
+    Tell(A::Message),
+    Ask(A::Message, Reply<A::Reply>),
+    Restart,
+    Stop
+}
+
+pub struct Addr<A: Actor> {
+    mailbox: Sender<Envelope<A>>
+}
+
+impl<A: Actor> Clone for Addr<A> {
+    fn clone(&self) -> Self {
+        Addr{mailbox: self.mailbox.clone()}
+    }
+}
+
+impl<A: Actor> Addr<A> {
+    // fails with the message back if the actor has already stopped
+    pub fn send(&self, msg: A::Message) -> Result<(), A::Message> {
+        self.mailbox.send(Envelope::Tell(msg)).map_err(|err| {
+            match err.0 {
+                Envelope::Tell(msg) => msg,
+                _ => unreachable!()
+            }
+        })
+    }
+
+    // the future fails if the handler panics or the actor stops before getting to it
+    pub fn ask(&self, msg: A::Message) -> Future<'static, A::Reply> {
+        let (promise, future) = Promise::new();
+        if let Err(err) = self.mailbox.send(Envelope::Ask(msg, Reply{promise: Some(promise)})) {
+            // the actor has stopped, dropping the envelope fails the future right away
+            drop(err);
+        }
+        future
+    }
+}
+
+// the actor runs until every Addr pointing to it is dropped
+pub fn spawn<A: Actor>(actor: A) -> Addr<A> {
+    let (sender, receiver) = channel();
+    let mut actor = Some(actor);
+    spawn_async(move || {
+        run(move || actor.take().expect("unsupervised actor can't be restarted"), receiver, None);
+    });
+    Addr{mailbox: sender}
+}
+
+fn run<A, Factory>(mut factory: Factory, receiver: Receiver<Envelope<A>>, supervisor: Option<(Arc<SupervisorState>, usize)>)
+    where A: Actor,
+          Factory: FnMut() -> A
+{
+    let mut actor = factory();
+    actor.started();
+    for envelope in receiver {
+        let result = match envelope {
+            Envelope::Tell(msg) => {
+                panic::catch_unwind(AssertUnwindSafe(|| {
+                    actor.handle(msg);
+                }))
+            },
+            Envelope::Ask(msg, reply) => {
+                match panic::catch_unwind(AssertUnwindSafe(|| actor.handle(msg))) {
+                    Ok(value) => {
+                        reply.set(value);
+                        Ok(())
+                    },
+                    Err(payload) => {
+                        reply.fail(ask_failure(&payload));
+                        Err(payload)
+                    }
+                }
+            },
+            Envelope::Restart => {
+                actor.stopped();
+                actor = factory();
+                actor.started();
+                continue;
+            },
+            Envelope::Stop => {break;}
+        };
+        if let Err(payload) = result {
+            match supervisor {
+                None => {panic::resume_unwind(payload);},
+                Some((ref state, child)) => {
+                    // shut down like an instance that is restarted or stopped
+                    actor.stopped();
+                    if !state.child_failed(child) {
+                        return;
+                    }
+                    actor = factory();
+                    actor.started();
+                }
+            }
+        }
+    }
+    actor.stopped();
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Strategy {
+    OneForOne,
+    AllForOne
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Escalation {
+    pub child: usize,
+    pub restarts: usize
+}
+
+// true restarts the child, false stops it
+type ChildControl = Box<dyn Fn(bool) + Send>;
+
+struct SupervisorState {
+    strategy: Strategy,
+    max_restarts: usize,
+    period: Duration,
+    restarts: Mutex<VecDeque<Instant>>,
+    children: Mutex<Vec<ChildControl>>,
+    escalate: Mutex<Option<Promise<'static, Escalation>>>
+}
+
+impl SupervisorState {
+    // returns whether the failed child should be restarted
+    fn child_failed(&self, child: usize) -> bool {
+        let restarts = {
+            let mut restarts = self.restarts.lock().unwrap();
+            let now = Instant::now();
+            while restarts.front().is_some_and(|&at| now.duration_since(at) > self.period) {
+                restarts.pop_front();
+            }
+            restarts.push_back(now);
+            restarts.len()
+        };
+        if restarts > self.max_restarts {
+            self.stop_children();
+            let promise = self.escalate.lock().unwrap().take();
+            if let Some(promise) = promise {
+                promise.set(Escalation{child, restarts: restarts - 1});
+            }
+            return false;
+        }
+        if self.strategy == Strategy::AllForOne {
+            let children = self.children.lock().unwrap();
+            children.iter()
+                .enumerate()
+                .filter(|&(idx, _)| idx != child)
+                .for_each(|(_, restart)| restart(true));
+        }
+        true
+    }
+
+    fn stop_children(&self) {
+        self.children.lock().unwrap().iter().for_each(|control| control(false));
+    }
+}
+
+pub struct Supervisor {
+    state: Arc<SupervisorState>,
+    escalated: SharedFuture<'static, Escalation>
+}
+
+impl Supervisor {
+    // escalates once more than max_restarts restarts happen within period
+    pub fn new(strategy: Strategy, max_restarts: usize, period: Duration) -> Supervisor {
+        let (promise, future) = Promise::new();
+        Supervisor {
+            state: Arc::new(SupervisorState {
+                strategy,
+                max_restarts,
+                period,
+                restarts: Mutex::new(VecDeque::new()),
+                children: Mutex::new(Vec::new()),
+                escalate: Mutex::new(Some(promise))
+            }),
+            escalated: future.share()
+        }
+    }
+
+    pub fn spawn<A, Factory>(&self, mut factory: Factory) -> Addr<A>
+        where A: Actor,
+              Factory: 'static + Send + FnMut() -> A
+    {
+        let (sender, receiver) = channel();
+        let child = {
+            let mut children = self.state.children.lock().unwrap();
+            let control = Mutex::new(sender.clone());
+            children.push(Box::new(move |restart| {
+                let envelope = if restart { Envelope::Restart } else { Envelope::Stop };
+                let _ = control.lock().unwrap().send(envelope);
+            }));
+            children.len() - 1
+        };
+        let state = self.state.clone();
+        spawn_async(move || {
+            run(&mut factory, receiver, Some((state, child)));
+        });
+        Addr{mailbox: sender}
+    }
+
+    pub fn escalated(&self) -> SharedFuture<'static, Escalation> {
+        self.escalated.clone()
+    }
+}
+
+impl Drop for Supervisor {
+    fn drop(&mut self) {
+        self.state.stop_children();
+    }
+}
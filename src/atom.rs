@@ -1,27 +1,145 @@
-use std::sync::atomic::{Ordering, AtomicUsize};
-use spinlock::{SpinRWLock, Spinlock};
-use std::sync::Arc;
-use std::mem;
+use sync::{Arc, Ordering, AtomicUsize};
+use spinlock::{SpinRWLock, SpinReadGuard, Spinlock, Locked, PoisonError};
+use core::fmt;
+use core::ops::Deref;
+#[cfg(feature = "std")]
+use std::collections::VecDeque;
+#[cfg(feature = "std")]
+use std::time::Instant;
 
+// the current slot may be empty, take and replace turn an Atom into a handoff slot.
+// Values are published through the slot locks, the index of the current slot only
+// needs acquire/release. The slot locks synchronize a load with the store that filled
+// its slot whatever the index ordering, so there is no cheaper ordering to offer
 pub struct Atom<T> {
     data: [SpinRWLock<Option<Arc<T>>>; 2],
     current: AtomicUsize,
-    write_guard: Spinlock<()>
+    write_guard: Spinlock<()>,
+    #[cfg(feature = "std")]
+    history: Option<History<T>>
+}
+
+// a value the atom held once, version as in load_versioned
+#[cfg(feature = "std")]
+pub struct HistoryEntry<T> {
+    pub value: Arc<T>,
+    pub version: u64,
+    pub stored_at: Instant
+}
+
+#[cfg(feature = "std")]
+impl<T> Clone for HistoryEntry<T> {
+    fn clone(&self) -> Self {
+        HistoryEntry{value: self.value.clone(), version: self.version, stored_at: self.stored_at}
+    }
+}
+
+#[cfg(feature = "std")]
+struct History<T> {
+    capacity: usize,
+    entries: Spinlock<VecDeque<HistoryEntry<T>>>
 }
 
 impl<T> Atom<T> {
     pub fn new(val: T) -> Self {
-        let ptr = Arc::new(val);
+        Atom::with_slot(Some(Arc::new(val)))
+    }
+
+    pub fn empty() -> Self {
+        Atom::with_slot(None)
+    }
+
+    fn with_slot(slot: Option<Arc<T>>) -> Self {
         Atom {
-            data: [SpinRWLock::new(Some(ptr)), SpinRWLock::new(None)],
+            data: [SpinRWLock::new(slot), SpinRWLock::new(None)],
             current: AtomicUsize::new(0),
-            write_guard: Spinlock::new(())
+            write_guard: Spinlock::new(()),
+            #[cfg(feature = "std")]
+            history: None
+        }
+    }
+
+    // keeps the last capacity values, the initial one included, alive for history()
+    #[cfg(feature = "std")]
+    pub fn with_history(val: T, capacity: usize) -> Self {
+        assert!(capacity > 0, "history capacity must be positive");
+        let atom = Atom {
+            history: Some(History{capacity, entries: Spinlock::new(VecDeque::with_capacity(capacity))}),
+            ..Atom::new(val)
+        };
+        atom.record(atom.data[0].read().unwrap_or_else(PoisonError::into_inner).as_ref(), 0);
+        atom
+    }
+
+    // oldest first, empty unless the atom was built with_history; take leaves no entry
+    #[cfg(feature = "std")]
+    pub fn history(&self) -> Vec<HistoryEntry<T>> {
+        self.history.as_ref().map_or_else(Vec::new, |history| {
+            history.entries.lock().expect("history is never shared").iter().cloned().collect()
+        })
+    }
+
+    #[cfg(feature = "std")]
+    fn record(&self, value: Option<&Arc<T>>, version: usize) {
+        if let (Some(history), Some(value)) = (self.history.as_ref(), value) {
+            let mut entries = history.entries.lock().expect("history is never shared");
+            if entries.len() == history.capacity {
+                entries.pop_front();
+            }
+            entries.push_back(HistoryEntry{value: value.clone(), version: version as u64, stored_at: Instant::now()});
         }
     }
 
+    // panics on an empty atom
     pub fn load(&self) -> Arc<T> {
-        let guard = self.data[self.get_idx()].read();
-        guard.as_ref().unwrap().clone()
+        self.try_load().expect("atom is empty")
+    }
+
+    pub fn try_load(&self) -> Option<Arc<T>> {
+        self.read_current().1.clone()
+    }
+
+    // the version goes up with every store, take and replace; panics on an empty atom
+    pub fn load_versioned(&self) -> (Arc<T>, u64) {
+        let (version, slot) = self.read_current();
+        (slot.clone().expect("atom is empty"), version as u64)
+    }
+
+    pub fn version(&self) -> u64 {
+        self.current.load(Ordering::Acquire) as u64
+    }
+
+    // borrows the value without touching the refcount; the slot stays pinned while the
+    // guard lives, so stores wait for it and one from the same thread deadlocks
+    pub fn load_ref(&self) -> AtomReadGuard<'_, T> {
+        self.try_load_ref().expect("atom is empty")
+    }
+
+    pub fn try_load_ref(&self) -> Option<AtomReadGuard<'_, T>> {
+        let (_, slot) = self.read_current();
+        slot.is_some().then_some(AtomReadGuard{slot})
+    }
+
+    fn read_current(&self) -> (usize, SpinReadGuard<'_, Option<Arc<T>>>) {
+        loop {
+            let seen = self.current.load(Ordering::Acquire);
+            // a slot only ever has a whole Arc swapped in, there's nothing to half update
+            let guard = self.data[seen % 2].read().unwrap_or_else(PoisonError::into_inner);
+            // a slot that stopped being current may be cleared before the read lock is
+            // taken, once it's held with no switch in between the slot stays as it is
+            if self.current.load(Ordering::Acquire) == seen {
+                return (seen, guard);
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.try_load().is_none()
+    }
+
+    // for hot read paths, make one per thread
+    pub fn cache(&self) -> Cache<'_, T> {
+        Cache{atom: self, value: None, version: None}
     }
 
     pub fn store_val(&self, val: T) {
@@ -29,18 +147,127 @@ impl<T> Atom<T> {
     }
 
     pub fn store(&self, val: Arc<T>) {
-        let _ = self.write_guard.lock();
-        let mut guard = self.data[(self.get_idx()+1)%2].write();
-        let mut wrapped = Some(val);
-        mem::swap(&mut wrapped, &mut *guard);
-        self.switch();
+        self.swap(Some(val));
+    }
+
+    // the previous value, which the atom no longer keeps a reference to unless its
+    // history does
+    pub fn replace(&self, val: T) -> Option<Arc<T>> {
+        self.swap(Some(Arc::new(val)))
+    }
+
+    // leaves the atom empty
+    pub fn take(&self) -> Option<Arc<T>> {
+        self.swap(None)
+    }
+
+    // false, leaving the atom as it is, if anything was stored after expected_version
+    // was read
+    pub fn store_if_version(&self, expected_version: u64, val: Arc<T>) -> bool {
+        let _writer = self.write_guard.lock();
+        if self.current.load(Ordering::Relaxed) as u64 != expected_version {
+            return false;
+        }
+        self.swap_locked(Some(val));
+        true
+    }
+
+    fn swap(&self, val: Option<Arc<T>>) -> Option<Arc<T>> {
+        let _writer = self.write_guard.lock();
+        self.swap_locked(val)
+    }
+
+    // with write_guard held, which also orders the stores among themselves
+    fn swap_locked(&self, val: Option<Arc<T>>) -> Option<Arc<T>> {
+        let version = self.current.load(Ordering::Relaxed);
+        let old = version % 2;
+        #[cfg(feature = "std")]
+        self.record(val.as_ref(), version.wrapping_add(1));
+        *self.data[(old + 1) % 2].write().unwrap_or_else(PoisonError::into_inner) = val;
+        self.current.fetch_add(1, Ordering::Release);
+        // waits for readers that picked the old slot before the switch
+        let previous = self.data[old].write().unwrap_or_else(PoisonError::into_inner).take();
+        previous
     }
 
     fn get_idx(&self) -> usize {
-        self.current.load(Ordering::SeqCst) % 2
+        self.current.load(Ordering::Acquire) % 2
+    }
+}
+
+impl<T: Clone> Atom<T> {
+    // f works on a copy and runs again if another store got in first, so it shouldn't
+    // have side effects; returns the value that was stored. Panics on an empty atom
+    pub fn modify<F: FnMut(&mut T)>(&self, mut f: F) -> Arc<T> {
+        loop {
+            let (current, version) = self.load_versioned();
+            let mut val = T::clone(&current);
+            f(&mut val);
+            let val = Arc::new(val);
+            if self.store_if_version(version, val.clone()) {
+                return val;
+            }
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Atom<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut out = f.debug_struct("Atom");
+        match self.data[self.get_idx()].try_peek(|value| {out.field("value", value);}) {
+            Some(()) => {},
+            None => {out.field("value", &Locked);}
+        }
+        out.finish()
     }
+}
+
+pub struct AtomReadGuard<'t, T: 't> {
+    // never empty, try_load_ref checks before handing it out
+    slot: SpinReadGuard<'t, Option<Arc<T>>>
+}
+
+impl<'t, T: 't> Deref for AtomReadGuard<'t, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.slot.as_deref().unwrap()
+    }
+}
+
+// the last value a thread loaded from an atom, while the version stays the same a load
+// only reads the atom's index, the slot locks and the refcount are left alone. A value
+// the atom replaced stays alive until the cache loads again
+pub struct Cache<'t, T: 't> {
+    atom: &'t Atom<T>,
+    value: Option<Arc<T>>,
+    // None until the first load
+    version: Option<u64>
+}
+
+impl<'t, T: 't> Cache<'t, T> {
+    // panics on an empty atom
+    pub fn load(&mut self) -> &Arc<T> {
+        self.try_load().expect("atom is empty")
+    }
+
+    pub fn try_load(&mut self) -> Option<&Arc<T>> {
+        let version = self.atom.version();
+        if self.version != Some(version) {
+            let (version, slot) = self.atom.read_current();
+            self.value = slot.clone();
+            self.version = Some(version as u64);
+        }
+        self.value.as_ref()
+    }
+
+    pub fn atom(&self) -> &'t Atom<T> {
+        self.atom
+    }
+}
 
-    fn switch(&self) {
-        self.current.fetch_add(1, Ordering::SeqCst);
+impl<'t, T: 't> Clone for Cache<'t, T> {
+    fn clone(&self) -> Self {
+        Cache{atom: self.atom, value: self.value.clone(), version: self.version}
     }
 }
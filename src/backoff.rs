@@ -0,0 +1,91 @@
+use sync;
+#[cfg(all(feature = "std", not(loom)))]
+use std::thread;
+#[cfg(all(feature = "std", not(loom)))]
+use std::time::Duration;
+
+// bursts of spin hints double up to 1 << SPIN_LIMIT, then the thread yields until
+// YIELD_LIMIT, then it sleeps for doubling intervals up to PARK_LIMIT_MICROS
+const SPIN_LIMIT: u32 = 6;
+const YIELD_LIMIT: u32 = 10;
+const PARK_LIMIT_MICROS: u64 = 1000;
+const STEP_LIMIT: u32 = YIELD_LIMIT + 10;
+
+// escalating wait for retry loops, reset it once the loop makes progress. Without std
+// there is nothing to yield to or sleep on, so every stage spins
+#[derive(Debug, Default, Clone)]
+pub struct Backoff {
+    step: u32
+}
+
+impl Backoff {
+    pub fn new() -> Backoff {
+        Backoff{step: 0}
+    }
+
+    pub fn reset(&mut self) {
+        self.step = 0;
+    }
+
+    // after a lost CAS race, where the winner is already done: never leaves the cpu
+    pub fn spin(&mut self) {
+        spin_burst(self.step.min(SPIN_LIMIT));
+        if self.step <= SPIN_LIMIT {
+            self.step += 1;
+        }
+    }
+
+    // while waiting for another thread to release something it may hold for long
+    pub fn snooze(&mut self) {
+        if self.step <= SPIN_LIMIT {
+            spin_burst(self.step);
+        } else if self.step <= YIELD_LIMIT {
+            yield_now();
+        } else {
+            park(PARK_LIMIT_MICROS.min(1 << (self.step - YIELD_LIMIT)));
+        }
+        if self.step < STEP_LIMIT {
+            self.step += 1;
+        }
+    }
+
+    // true once snooze has moved past yielding, waiters that have a way to be woken
+    // up should block on it instead
+    pub fn is_completed(&self) -> bool {
+        self.step > YIELD_LIMIT
+    }
+}
+
+#[cfg(not(loom))]
+fn spin_burst(step: u32) {
+    for _ in 0..1u32 << step {
+        sync::spin_loop();
+    }
+}
+
+// every spin is a yield under loom, one per step keeps the explored state space small
+#[cfg(loom)]
+fn spin_burst(_step: u32) {
+    sync::spin_loop();
+}
+
+#[cfg(all(feature = "std", not(loom)))]
+fn yield_now() {
+    thread::yield_now();
+}
+
+#[cfg(not(all(feature = "std", not(loom))))]
+fn yield_now() {
+    sync::spin_loop();
+}
+
+// sleeps rather than thread::park, so an unpark meant for the caller isn't swallowed
+#[cfg(all(feature = "std", not(loom)))]
+fn park(micros: u64) {
+    thread::sleep(Duration::from_micros(micros));
+}
+
+#[cfg(not(all(feature = "std", not(loom))))]
+fn park(_micros: u64) {
+    sync::spin_loop();
+}
@@ -0,0 +1,133 @@
+use std::sync::{Arc, Mutex};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use future::Future;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum BreakerError<E> {
+    Open,
+    Inner(E)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BreakerState {
+    Closed,
+    Open,
+    HalfOpen
+}
+
+enum Mode {
+    Closed,
+    Open(Instant),
+    // a single probe call is let through, everything else fails fast until it finishes
+    HalfOpen
+}
+
+struct Inner {
+    mode: Mode,
+    failures: VecDeque<Instant>,
+    failure_threshold: usize,
+    window: Duration,
+    cooldown: Duration
+}
+
+impl Inner {
+    fn permit(&mut self) -> bool {
+        match self.mode {
+            Mode::Closed => true,
+            Mode::Open(since) if since.elapsed() >= self.cooldown => {
+                self.mode = Mode::HalfOpen;
+                true
+            },
+            _ => false
+        }
+    }
+
+    fn record(&mut self, success: bool) {
+        let now = Instant::now();
+        match self.mode {
+            Mode::HalfOpen => {
+                self.failures.clear();
+                self.mode = if success { Mode::Closed } else { Mode::Open(now) };
+            },
+            Mode::Closed if !success => {
+                while self.failures.front().is_some_and(|&at| now.duration_since(at) > self.window) {
+                    self.failures.pop_front();
+                }
+                self.failures.push_back(now);
+                if self.failures.len() >= self.failure_threshold {
+                    self.failures.clear();
+                    self.mode = Mode::Open(now);
+                }
+            },
+            _ => {}
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct CircuitBreaker {
+    inner: Arc<Mutex<Inner>>
+}
+
+impl CircuitBreaker {
+    // opens after failure_threshold failures within window, probes again after cooldown
+    pub fn new(failure_threshold: usize, window: Duration, cooldown: Duration) -> CircuitBreaker {
+        assert!(failure_threshold > 0, "failure threshold must be positive");
+        CircuitBreaker {
+            inner: Arc::new(Mutex::new(Inner {
+                mode: Mode::Closed,
+                failures: VecDeque::new(),
+                failure_threshold,
+                window,
+                cooldown
+            }))
+        }
+    }
+
+    pub fn state(&self) -> BreakerState {
+        match self.inner.lock().unwrap().mode {
+            Mode::Closed => BreakerState::Closed,
+            Mode::Open(_) => BreakerState::Open,
+            Mode::HalfOpen => BreakerState::HalfOpen
+        }
+    }
+
+    // f isn't called at all while the breaker is open
+    pub fn call<'t, T, E, Func>(&self, f: Func) -> Future<'t, Result<T, BreakerError<E>>>
+        where T: 't + Send,
+              E: 't + Send,
+              Func: FnOnce() -> Future<'t, Result<T, E>>
+    {
+        if !self.inner.lock().unwrap().permit() {
+            return Future::new(Err(BreakerError::Open));
+        }
+        let call = Call{inner: Some(self.inner.clone())};
+        f().apply(move |result| {
+            call.record(result.is_ok());
+            result.map_err(BreakerError::Inner)
+        })
+    }
+}
+
+// a permitted call that hasn't been recorded yet; dropping it counts as a failure, so
+// a panic in f or upstream or a dropped promise can't leave a probe open forever
+struct Call {
+    inner: Option<Arc<Mutex<Inner>>>
+}
+
+impl Call {
+    fn record(mut self, success: bool) {
+        if let Some(inner) = self.inner.take() {
+            inner.lock().unwrap_or_else(|err| err.into_inner()).record(success);
+        }
+    }
+}
+
+impl Drop for Call {
+    fn drop(&mut self) {
+        if let Some(inner) = self.inner.take() {
+            inner.lock().unwrap_or_else(|err| err.into_inner()).record(false);
+        }
+    }
+}
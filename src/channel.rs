@@ -0,0 +1,156 @@
+use std::collections::VecDeque;
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, RecvError, RecvTimeoutError, SendError, TryRecvError};
+use std::time::{Duration, Instant};
+use event::{Waitable, Wake, Timer, select_waitables};
+use future::Future;
+use io::offload;
+
+pub trait ReceiverExt<T>: Sized {
+    // parks a blocking thread in recv, the receiver comes back with the message
+    // so it can be used again
+    fn recv_future(self) -> Future<'static, Result<(T, Self), RecvError>>;
+}
+
+impl<T: 'static + Send> ReceiverExt<T> for mpsc::Receiver<T> {
+    fn recv_future(self) -> Future<'static, Result<(T, mpsc::Receiver<T>), RecvError>> {
+        offload(move || self.recv().map(|msg| (msg, self)))
+    }
+}
+
+struct Queue<T> {
+    messages: VecDeque<T>,
+    senders: usize,
+    receiver: bool,
+    watchers: Vec<Wake>
+}
+
+// an unbounded mpsc channel like std's, except that the receiver is Waitable and can be
+// selected on together with events, futures and timers; the errors are std's
+pub fn unbounded<T>() -> (Sender<T>, Receiver<T>) {
+    let queue = Arc::new(Mutex::new(Queue {
+        messages: VecDeque::new(),
+        senders: 1,
+        receiver: true,
+        watchers: Vec::new()
+    }));
+    (Sender{queue: queue.clone()}, Receiver{queue})
+}
+
+pub struct Sender<T> {
+    queue: Arc<Mutex<Queue<T>>>
+}
+
+impl<T> Sender<T> {
+    // fails with the message back once the receiver is gone
+    pub fn send(&self, msg: T) -> Result<(), SendError<T>> {
+        let watchers = {
+            let mut queue = self.queue.lock().unwrap();
+            if !queue.receiver {
+                return Err(SendError(msg));
+            }
+            queue.messages.push_back(msg);
+            // woken outside of the lock, like the event watchers
+            queue.watchers.clone()
+        };
+        watchers.iter().for_each(|wake| wake());
+        Ok(())
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.queue.lock().unwrap().senders += 1;
+        Sender{queue: self.queue.clone()}
+    }
+}
+
+// the last sender leaving disconnects the channel, which wakes the receiver too
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let watchers = {
+            let mut queue = self.queue.lock().unwrap();
+            queue.senders -= 1;
+            if queue.senders > 0 {
+                return;
+            }
+            queue.watchers.clone()
+        };
+        watchers.iter().for_each(|wake| wake());
+    }
+}
+
+pub struct Receiver<T> {
+    queue: Arc<Mutex<Queue<T>>>
+}
+
+impl<T> Receiver<T> {
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        let mut queue = self.queue.lock().unwrap();
+        match queue.messages.pop_front() {
+            Some(msg) => Ok(msg),
+            None if queue.senders == 0 => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty)
+        }
+    }
+
+    // messages sent before the last sender left are still handed out
+    pub fn recv(&self) -> Result<T, RecvError> {
+        loop {
+            match self.try_recv() {
+                Ok(msg) => {return Ok(msg);},
+                Err(TryRecvError::Disconnected) => {return Err(RecvError);},
+                Err(TryRecvError::Empty) => {select_waitables(&[self]);}
+            }
+        }
+    }
+
+    pub fn recv_deadline(&self, deadline: Instant) -> Result<T, RecvTimeoutError> {
+        let timer = Timer::at(deadline);
+        loop {
+            match self.try_recv() {
+                Ok(msg) => {return Ok(msg);},
+                Err(TryRecvError::Disconnected) => {return Err(RecvTimeoutError::Disconnected);},
+                Err(TryRecvError::Empty) => {
+                    if select_waitables(&[self, &timer]) == 1 && !self.is_ready() {
+                        return Err(RecvTimeoutError::Timeout);
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        self.recv_deadline(Instant::now() + timeout)
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        // nobody will take them any more, senders find out on their next send; they are
+        // dropped outside of the lock, their drop may send to this very channel
+        let _messages = {
+            let mut queue = self.queue.lock().unwrap();
+            queue.receiver = false;
+            queue.watchers.clear();
+            mem::take(&mut queue.messages)
+        };
+    }
+}
+
+// ready while a message is queued or once every sender is gone, recv won't block then
+impl<T> Waitable for Receiver<T> {
+    fn is_ready(&self) -> bool {
+        let queue = self.queue.lock().unwrap();
+        !queue.messages.is_empty() || queue.senders == 0
+    }
+
+    fn watch(&self, wake: &Wake) {
+        self.queue.lock().unwrap().watchers.push(wake.clone());
+    }
+
+    fn unwatch(&self, wake: &Wake) {
+        self.queue.lock().unwrap().watchers.retain(|watcher| !Arc::ptr_eq(watcher, wake));
+    }
+}
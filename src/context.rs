@@ -0,0 +1,63 @@
+use std::any::{Any, TypeId};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+type Values = HashMap<TypeId, Arc<dyn Any + Send + Sync>>;
+
+// an immutable snapshot of the task-locals visible on a thread, keyed by type
+#[derive(Clone, Default)]
+pub struct Context {
+    values: Option<Arc<Values>>
+}
+
+thread_local! {
+    static CURRENT: RefCell<Context> = RefCell::new(Context::default());
+}
+
+// puts the previous context back, also when unwinding
+struct Restore(Option<Context>);
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        let previous = self.0.take().unwrap();
+        CURRENT.with(|current| *current.borrow_mut() = previous);
+    }
+}
+
+impl Context {
+    pub fn current() -> Context {
+        CURRENT.with(|current| current.borrow().clone())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_none()
+    }
+
+    // runs f with self as the current context of this thread
+    pub fn run<R, Func: FnOnce() -> R>(&self, f: Func) -> R {
+        let previous = CURRENT.with(|current| current.replace(self.clone()));
+        let _restore = Restore(Some(previous));
+        f()
+    }
+}
+
+// runs f with value added to the current context, continuations registered inside
+// see it as well wherever they run
+pub fn with_value<T, R, Func>(value: T, f: Func) -> R
+    where T: Any + Send + Sync,
+          Func: FnOnce() -> R
+{
+    let mut values = Context::current().values.map(|values| (*values).clone()).unwrap_or_default();
+    values.insert(TypeId::of::<T>(), Arc::new(value));
+    Context{values: Some(Arc::new(values))}.run(f)
+}
+
+pub fn get<T: Any + Send + Sync + Clone>() -> Option<T> {
+    CURRENT.with(|current| {
+        current.borrow().values.as_ref()
+            .and_then(|values| values.get(&TypeId::of::<T>()))
+            .and_then(|value| value.downcast_ref::<T>())
+            .cloned()
+    })
+}
@@ -0,0 +1,68 @@
+use std::fmt;
+use std::sync::atomic::{AtomicI64, AtomicUsize, Ordering};
+use std::thread;
+
+// threads take shards round robin in the order they first touch any counter
+static NEXT_SHARD: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static SHARD: usize = NEXT_SHARD.fetch_add(1, Ordering::Relaxed);
+}
+
+// one cell per cache line, so threads adding to neighbouring cells don't contend
+#[repr(align(128))]
+#[derive(Default)]
+struct Cell(AtomicI64);
+
+// adds go to the calling thread's cell and sum adds the cells up, which makes adding
+// cheap under contention and reading proportionally slower; a sum taken while adds run
+// may miss some of them, but counts every add that finished before it started
+pub struct ShardedCounter {
+    cells: Box<[Cell]>
+}
+
+impl ShardedCounter {
+    // a cell per core, twice over so that threads sharing a cell are rare
+    pub fn new() -> ShardedCounter {
+        ShardedCounter::with_shards(2 * thread::available_parallelism().map_or(1, |n| n.get()))
+    }
+
+    pub fn with_shards(shards: usize) -> ShardedCounter {
+        assert!(shards > 0, "counter needs at least one shard");
+        ShardedCounter{cells: (0..shards).map(|_| Cell::default()).collect()}
+    }
+
+    pub fn add(&self, delta: i64) {
+        let shard = SHARD.with(|shard| *shard) % self.cells.len();
+        self.cells[shard].0.fetch_add(delta, Ordering::Relaxed);
+    }
+
+    pub fn increment(&self) {
+        self.add(1);
+    }
+
+    pub fn decrement(&self) {
+        self.add(-1);
+    }
+
+    pub fn sum(&self) -> i64 {
+        self.cells.iter().map(|cell| cell.0.load(Ordering::Relaxed)).fold(0, i64::wrapping_add)
+    }
+
+    // the sum up to now, adds racing with it land either in it or after it
+    pub fn take(&self) -> i64 {
+        self.cells.iter().map(|cell| cell.0.swap(0, Ordering::Relaxed)).fold(0, i64::wrapping_add)
+    }
+}
+
+impl Default for ShardedCounter {
+    fn default() -> ShardedCounter {
+        ShardedCounter::new()
+    }
+}
+
+impl fmt::Debug for ShardedCounter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ShardedCounter").field("sum", &self.sum()).finish()
+    }
+}
@@ -1,26 +1,128 @@
-use std::sync::{Mutex, Condvar};
+#[cfg(feature = "std")]
+use sync::{Arc, Mutex, Condvar};
+#[cfg(feature = "std")]
+use std::time::{Duration, Instant};
+#[cfg(feature = "std")]
+use std::sync::Arc as StdArc;
+#[cfg(not(feature = "std"))]
+use sync::{self, AtomicBool, AtomicUsize, Ordering};
+#[cfg(feature = "std")]
+use spinlock::Locked;
+use core::fmt;
 
+#[cfg(feature = "std")]
+struct ParkState {
+    var: Condvar,
+    notified: Mutex<bool>
+}
+
+// a token based blocker for one thread: unpark before park makes the next park
+// return at once, several unparks before it collapse into one
+#[cfg(feature = "std")]
+pub struct Parker {
+    state: Arc<ParkState>
+}
+
+// the waking half of a Parker, can be cloned and sent to other threads
+#[cfg(feature = "std")]
+#[derive(Clone)]
+pub struct Unparker {
+    state: Arc<ParkState>
+}
+
+#[cfg(feature = "std")]
+impl Parker {
+    pub fn new() -> Parker {
+        Parker {
+            state: Arc::new(ParkState {
+                var: Condvar::new(),
+                notified: Mutex::new(false)
+            })
+        }
+    }
+
+    pub fn unparker(&self) -> Unparker {
+        Unparker{state: self.state.clone()}
+    }
+
+    // returns at once if unparked since the last park
+    pub fn park(&self) {
+        let mut notified = self.state.notified.lock().unwrap();
+        while !*notified {
+            notified = self.state.var.wait(notified).unwrap();
+        }
+        *notified = false;
+    }
+
+    // false if the deadline passed first
+    pub fn park_deadline(&self, deadline: Instant) -> bool {
+        let mut notified = self.state.notified.lock().unwrap();
+        while !*notified {
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            notified = self.state.var.wait_timeout(notified, deadline - now).unwrap().0;
+        }
+        *notified = false;
+        true
+    }
+
+    pub fn park_timeout(&self, timeout: Duration) -> bool {
+        self.park_deadline(Instant::now() + timeout)
+    }
+}
+
+#[cfg(feature = "std")]
+impl Default for Parker {
+    fn default() -> Parker {
+        Parker::new()
+    }
+}
+
+#[cfg(feature = "std")]
+impl Unparker {
+    pub fn unpark(&self) {
+        *self.state.notified.lock().unwrap() = true;
+        self.state.var.notify_one();
+    }
+}
+
+#[cfg(feature = "std")]
+struct EventState {
+    set: bool,
+    // bumped by every signal and pulse
+    generation: usize,
+    watchers: Vec<Wake>
+}
+
+#[cfg(feature = "std")]
 pub struct Event {
     var: Condvar,
-    set: Mutex<bool>
+    state: Mutex<EventState>
 }
 
+#[cfg(feature = "std")]
 impl Event {
     pub fn new() -> Event {
         Event {
-            set: Mutex::new(false),
+            state: Mutex::new(EventState {
+                set: false,
+                generation: 0,
+                watchers: Vec::new()
+            }),
             var: Condvar::new()
         }
     }
 
     pub fn reset(self: &Event) {
-        *(self.set.lock().unwrap()) = false;
+        self.state.lock().unwrap().set = false;
     }
 
     pub fn wait(self: &Event) {
-        let mut lock = self.set.lock().unwrap();
+        let mut lock = self.state.lock().unwrap();
         loop {
-            if *lock {
+            if lock.set {
                 break;
             } else {
                 lock = self.var.wait(lock).unwrap();
@@ -28,9 +130,326 @@ impl Event {
         }
     }
 
+    // false if the deadline passed before the event was signaled
+    pub fn wait_deadline(self: &Event, deadline: Instant) -> bool {
+        let mut lock = self.state.lock().unwrap();
+        loop {
+            if lock.set {
+                return true;
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            lock = self.var.wait_timeout(lock, deadline - now).unwrap().0;
+        }
+    }
+
+    pub fn wait_timeout(self: &Event, timeout: Duration) -> bool {
+        self.wait_deadline(Instant::now() + timeout)
+    }
+
+    pub fn signal(self: &Event) {
+        let mut lock = self.state.lock().unwrap();
+        lock.set = true;
+        lock.generation = lock.generation.wrapping_add(1);
+        self.var.notify_all();
+        lock.watchers.iter().for_each(|wake| wake());
+    }
+
+    pub fn is_set(self: &Event) -> bool {
+        self.state.lock().unwrap().set
+    }
+
+    // index of the first set event, blocking until one of them is signaled
+    pub fn wait_any(events: &[&Event]) -> usize {
+        let waitables: Vec<&dyn Waitable> = events.iter().map(|&event| event as &dyn Waitable).collect();
+        select_waitables(&waitables)
+    }
+
+    pub fn wait_all(events: &[&Event]) {
+        events.iter().for_each(|event| event.wait());
+    }
+
+    // wakes whoever waits for the next generation without leaving the event set
+    pub fn pulse(self: &Event) {
+        let mut lock = self.state.lock().unwrap();
+        lock.generation = lock.generation.wrapping_add(1);
+        self.var.notify_all();
+    }
+
+    pub fn generation(self: &Event) -> usize {
+        self.state.lock().unwrap().generation
+    }
+
+    // returns as soon as the generation differs from seen, so a pulse that happened
+    // between reading the generation and calling this isn't missed
+    pub fn wait_since(self: &Event, seen: usize) -> usize {
+        let mut lock = self.state.lock().unwrap();
+        while lock.generation == seen {
+            lock = self.var.wait(lock).unwrap();
+        }
+        lock.generation
+    }
+
+    pub fn wait_for_next(self: &Event) -> usize {
+        let lock = self.state.lock().unwrap();
+        let seen = lock.generation;
+        drop(lock);
+        self.wait_since(seen)
+    }
+}
+
+#[cfg(feature = "std")]
+impl fmt::Debug for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut out = f.debug_struct("Event");
+        match self.state.try_lock() {
+            Ok(state) => out.field("set", &state.set).field("generation", &state.generation),
+            Err(_) => out.field("state", &Locked)
+        }.finish()
+    }
+}
+
+// called whenever the source it was registered with may have become ready
+#[cfg(feature = "std")]
+pub type Wake = StdArc<dyn Fn() + Send + Sync>;
+
+// anything select_waitables can block on
+#[cfg(feature = "std")]
+pub trait Waitable {
+    fn is_ready(&self) -> bool;
+
+    // wake has to be called once this becomes ready after the call
+    fn watch(&self, wake: &Wake);
+
+    // sources that can't forget a registration leave it in place, waking it is harmless
+    fn unwatch(&self, _wake: &Wake) {}
+
+    // time based sources are ready at this point without any wake
+    fn deadline(&self) -> Option<Instant> {
+        None
+    }
+}
+
+#[cfg(feature = "std")]
+impl Waitable for Event {
+    fn is_ready(&self) -> bool {
+        self.is_set()
+    }
+
+    fn watch(&self, wake: &Wake) {
+        self.state.lock().unwrap().watchers.push(wake.clone());
+    }
+
+    fn unwatch(&self, wake: &Wake) {
+        self.state.lock().unwrap().watchers.retain(|watcher| !StdArc::ptr_eq(watcher, wake));
+    }
+}
+
+// becomes ready once its instant has passed
+#[cfg(feature = "std")]
+#[derive(Clone, Copy, Debug)]
+pub struct Timer {
+    at: Instant
+}
+
+#[cfg(feature = "std")]
+impl Timer {
+    pub fn at(at: Instant) -> Timer {
+        Timer{at}
+    }
+
+    pub fn after(timeout: Duration) -> Timer {
+        Timer::at(Instant::now() + timeout)
+    }
+}
+
+#[cfg(feature = "std")]
+impl Waitable for Timer {
+    fn is_ready(&self) -> bool {
+        Instant::now() >= self.at
+    }
+
+    fn watch(&self, _wake: &Wake) {}
+
+    fn deadline(&self) -> Option<Instant> {
+        Some(self.at)
+    }
+}
+
+// blocks until one of sources is ready and returns the index of the first ready one
+#[cfg(feature = "std")]
+pub fn select_waitables(sources: &[&dyn Waitable]) -> usize {
+    assert!(!sources.is_empty(), "select needs at least one source");
+    let parker = Parker::new();
+    let wake: Wake = {
+        let unparker = parker.unparker();
+        StdArc::new(move || unparker.unpark())
+    };
+    sources.iter().for_each(|source| source.watch(&wake));
+    let deadline = sources.iter().filter_map(|source| source.deadline()).min();
+    let ready = loop {
+        if let Some(idx) = sources.iter().position(|source| source.is_ready()) {
+            break idx;
+        }
+        match deadline {
+            Some(deadline) => {parker.park_deadline(deadline);},
+            None => {parker.park();}
+        }
+    };
+    sources.iter().for_each(|source| source.unwatch(&wake));
+    ready
+}
+
+// nothing to park on without std, so waiters spin
+#[cfg(not(feature = "std"))]
+pub struct Event {
+    set: AtomicBool,
+    generation: AtomicUsize
+}
+
+#[cfg(not(feature = "std"))]
+impl Event {
+    pub fn new() -> Event {
+        Event {
+            set: AtomicBool::new(false),
+            generation: AtomicUsize::new(0)
+        }
+    }
+
+    pub fn pulse(self: &Event) {
+        self.generation.fetch_add(1, Ordering::AcqRel);
+    }
+
+    pub fn generation(self: &Event) -> usize {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    pub fn wait_since(self: &Event, seen: usize) -> usize {
+        loop {
+            let generation = self.generation();
+            if generation != seen {
+                return generation;
+            }
+            sync::spin_loop();
+        }
+    }
+
+    pub fn wait_for_next(self: &Event) -> usize {
+        self.wait_since(self.generation())
+    }
+
+    pub fn reset(self: &Event) {
+        self.set.store(false, Ordering::Release);
+    }
+
+    pub fn wait(self: &Event) {
+        while !self.set.load(Ordering::Acquire) {
+            sync::spin_loop();
+        }
+    }
+
     pub fn signal(self: &Event) {
-        let mut lock = self.set.lock().unwrap();
-        *lock = true;
+        self.set.store(true, Ordering::Release);
+        self.generation.fetch_add(1, Ordering::AcqRel);
+    }
+
+    pub fn is_set(self: &Event) -> bool {
+        self.set.load(Ordering::Acquire)
+    }
+
+    pub fn wait_any(events: &[&Event]) -> usize {
+        assert!(!events.is_empty(), "wait_any needs at least one event");
+        loop {
+            if let Some(idx) = events.iter().position(|event| event.is_set()) {
+                return idx;
+            }
+            sync::spin_loop();
+        }
+    }
+
+    pub fn wait_all(events: &[&Event]) {
+        events.iter().for_each(|event| event.wait());
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl fmt::Debug for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Event")
+            .field("set", &self.is_set())
+            .field("generation", &self.generation())
+            .finish()
+    }
+}
+
+// waiters pass while it is open and block while it is closed; unlike Event it can be
+// closed again, e.g. to pause and resume a set of workers
+#[cfg(feature = "std")]
+pub struct Gate {
+    var: Condvar,
+    open: Mutex<bool>
+}
+
+#[cfg(feature = "std")]
+impl Gate {
+    pub fn new(open: bool) -> Gate {
+        Gate {
+            open: Mutex::new(open),
+            var: Condvar::new()
+        }
+    }
+
+    pub fn open(self: &Gate) {
+        *self.open.lock().unwrap() = true;
         self.var.notify_all();
     }
+
+    pub fn close(self: &Gate) {
+        *self.open.lock().unwrap() = false;
+    }
+
+    pub fn is_open(self: &Gate) -> bool {
+        *self.open.lock().unwrap()
+    }
+
+    pub fn wait(self: &Gate) {
+        let mut open = self.open.lock().unwrap();
+        while !*open {
+            open = self.var.wait(open).unwrap();
+        }
+    }
+}
+
+#[cfg(not(feature = "std"))]
+pub struct Gate {
+    open: AtomicBool
+}
+
+#[cfg(not(feature = "std"))]
+impl Gate {
+    pub fn new(open: bool) -> Gate {
+        Gate {
+            open: AtomicBool::new(open)
+        }
+    }
+
+    pub fn open(self: &Gate) {
+        self.open.store(true, Ordering::Release);
+    }
+
+    pub fn close(self: &Gate) {
+        self.open.store(false, Ordering::Release);
+    }
+
+    pub fn is_open(self: &Gate) -> bool {
+        self.open.load(Ordering::Acquire)
+    }
+
+    pub fn wait(self: &Gate) {
+        while !self.is_open() {
+            sync::spin_loop();
+        }
+    }
 }
@@ -1,13 +1,139 @@
-use std::sync::{Arc, Mutex};
-use spinlock::Spinlock;
-use event::Event;
+use std::sync::{Mutex, OnceLock};
+use std::sync::Arc as StdArc;
+use std::sync::mpsc::{channel, Sender};
+use std::sync::atomic::{AtomicUsize, AtomicBool, Ordering};
+use sync::Arc;
+use spinlock::{Spinlock, SpinlockGuard, Locked, Release};
+use event::{Event, Waitable, Wake};
 use std::mem;
+use std::fmt;
+use std::iter::FromIterator;
+use std::marker::PhantomData;
+use std::any::Any;
+use std::ffi::c_void;
+use std::panic::{self, AssertUnwindSafe};
+use std::thread;
+use std::process;
+use std::backtrace::Backtrace;
+use std::time::{Duration, Instant};
+use instrument;
+use context::Context;
+use threads::ThreadConfig;
+#[cfg(feature = "metrics")]
+use metrics;
 
 use future::FutureValue::*;
 
+pub type PanicPayload = Box<dyn Any + Send>;
+
+// what panic!() was called with, if it was a message
+pub(crate) fn panic_message(payload: &PanicPayload) -> Option<&str> {
+    payload.downcast_ref::<&str>().copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+}
+
+// orders callbacks of one future and spawns queued behind a scope's concurrency limit:
+// higher ones go first, equal ones in the order they came in
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Default)]
+pub enum Priority {
+    Background,
+    #[default]
+    Normal,
+    Latency
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CallbackPanicPolicy {
+    // run the remaining callbacks, then re-raise the first panic in the completing thread
+    Continue,
+    Abort
+}
+
+static CALLBACK_PANIC_POLICY: AtomicUsize = AtomicUsize::new(CallbackPanicPolicy::Continue as usize);
+
+pub fn set_callback_panic_policy(policy: CallbackPanicPolicy) {
+    CALLBACK_PANIC_POLICY.store(policy as usize, Ordering::Relaxed);
+}
+
+pub fn callback_panic_policy() -> CallbackPanicPolicy {
+    if CALLBACK_PANIC_POLICY.load(Ordering::Relaxed) == CallbackPanicPolicy::Abort as usize {
+        CallbackPanicPolicy::Abort
+    } else {
+        CallbackPanicPolicy::Continue
+    }
+}
+
+// SharedFuture handles are only Send when T: Sync, so whichever thread holds the
+// state besides them can only move the Arc around or drop it
+struct SharedValue<T>(Arc<T>);
+
+unsafe impl<T: Send> Send for SharedValue<T> {}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CallbackDispatch {
+    // callbacks run in the thread calling set()
+    Inline,
+    // callbacks of futures from Promise::new_static and spawn_async are queued to a
+    // dedicated thread instead; borrowing futures stay inline, the thread could
+    // outlive what they borrow
+    Dispatcher
+}
+
+static CALLBACK_DISPATCH: AtomicUsize = AtomicUsize::new(CallbackDispatch::Inline as usize);
+
+pub fn set_callback_dispatch(dispatch: CallbackDispatch) {
+    CALLBACK_DISPATCH.store(dispatch as usize, Ordering::Relaxed);
+}
+
+pub fn callback_dispatch() -> CallbackDispatch {
+    if CALLBACK_DISPATCH.load(Ordering::Relaxed) == CallbackDispatch::Dispatcher as usize {
+        CallbackDispatch::Dispatcher
+    } else {
+        CallbackDispatch::Inline
+    }
+}
+
+static DISPATCHER: OnceLock<Mutex<Sender<Job<'static>>>> = OnceLock::new();
+
+// only instantiated for 'static futures of Send values, see Promise::new_static
+fn dispatch<T: 'static + Send>(holder: &StateHolder<'static, T>, callbacks: Vec<Callback<'static, T>>) {
+    let holder = holder.clone();
+    let sender = DISPATCHER.get_or_init(|| {
+        let (sender, receiver) = channel::<Job<'static>>();
+        ThreadConfig::new().name_prefix("callbacks").spawn(move || {
+            receiver.into_iter().for_each(|job| job());
+        }).expect("failed to spawn callback dispatcher");
+        Mutex::new(sender)
+    });
+    let job: Job<'static> = Box::new(move || {
+        // nobody to re-raise to here, the panic hook has reported it already
+        callbacks.into_iter().for_each(|f| {
+            #[cfg(feature = "metrics")]
+            metrics::callback_executed();
+            if panic::catch_unwind(AssertUnwindSafe(|| holder.run_callback(f))).is_err()
+                && callback_panic_policy() == CallbackPanicPolicy::Abort {
+                process::abort();
+            }
+        });
+    });
+    sender.lock().unwrap().send(job).expect("callback dispatcher is gone");
+}
+
+static LEAK_DIAGNOSTICS: AtomicBool = AtomicBool::new(false);
+
+// logs a backtrace whenever the last handle of a still pending future is dropped
+// without anything waiting on it
+pub fn set_leak_diagnostics(enabled: bool) {
+    LEAK_DIAGNOSTICS.store(enabled, Ordering::Relaxed);
+}
+
 enum FutureValue<T> {
     ValEmpty,
     ValSet(T),
+    ValShared(SharedValue<T>),
+    ValPanicked(PanicPayload),
+    // a panic seen through share(), down to its message so every reader gets a copy
+    ValSharedPanic(String),
     ValMoved,
 }
 
@@ -19,149 +145,505 @@ impl<T> FutureValue<T> {
         }
     }
 
-    fn take(&mut self) -> T {
+    fn take(&mut self) -> thread::Result<T> {
         let mut new = ValMoved;
         mem::swap(&mut new, self);
         match new {
-            ValSet(x) => x,
+            ValSet(x) => Ok(x),
+            ValPanicked(payload) => Err(payload),
+            ValShared(_) | ValSharedPanic(_) => {panic!("value is shared");},
             _ => {panic!("value has been moved");}
         }
     }
 
-    fn read(&self) -> &T {
+    // the payload can't be handed out to several readers, so they get a fresh one with
+    // the same message
+    fn share(&mut self) -> thread::Result<Arc<T>> {
+        *self = match mem::replace(self, ValMoved) {
+            ValSet(x) => ValShared(SharedValue(Arc::new(x))),
+            ValPanicked(payload) => {
+                ValSharedPanic(panic_message(&payload).unwrap_or("future producer panicked").to_string())
+            },
+            other => other
+        };
         match *self {
-            ValSet(ref x) => x,
+            ValShared(ref x) => Ok(x.0.clone()),
+            ValSharedPanic(ref message) => Err(Box::new(message.clone())),
             _ => {panic!("value has been moved");}
         }
     }
 
-    fn put(&mut self, val: T) {
+    // undoes share() when no Arc is handed out any more, false otherwise
+    fn unshare(&mut self) -> bool {
+        match mem::replace(self, ValMoved) {
+            ValShared(SharedValue(value)) => match Arc::try_unwrap(value) {
+                Ok(value) => {
+                    *self = ValSet(value);
+                    true
+                },
+                Err(value) => {
+                    *self = ValShared(SharedValue(value));
+                    false
+                }
+            },
+            ValSharedPanic(message) => {
+                *self = ValPanicked(Box::new(message));
+                true
+            },
+            other => {
+                *self = other;
+                true
+            }
+        }
+    }
+
+    fn put(&mut self, val: FutureValue<T>) {
         match *self {
-            ValSet(_) => {panic!("double set on same future state");},
+            ValEmpty => {},
             ValMoved => {panic!("value already moved");},
-            _ => {}
+            _ => {panic!("double set on same future state");}
         }
-        *self = ValSet(val);
+        *self = val;
     }
 }
 
+type Callback<'t, T> = Box<dyn 't + FnOnce(&StateHolder<'t, T>) + Send>;
+
+type Dispatch<'t, T> = fn(&StateHolder<'t, T>, Vec<Callback<'t, T>>);
+
 struct FutureState<'t, T>
     where T: 't
 {
     value: FutureValue<T>,
-    callbacks: Vec<Box<dyn 't + FnOnce(&StateHolder<'t, T>) -> () + Send>>,
-    ready_event: Option<Arc<Event>>
+    // run in this order: higher priority first, equal ones in registration order
+    callbacks: Vec<(Priority, Callback<'t, T>)>,
+    ready_event: Option<Arc<Event>>,
+    // select_waitables registrations, kept apart from callbacks so they can be removed
+    watchers: Vec<Wake>,
+    // callbacks taken out of the queue that haven't returned yet, they may still hold &T
+    running: usize,
+    // set for states the callback dispatcher may take over
+    dispatch: Option<Dispatch<'t, T>>
 }
 
 // all calbacks will be executed once, so
 unsafe impl<'t, T: Sync> Sync for FutureState<'t, T> {}
 
+// who still listens to a future: its handles and its queued callbacks. Kept apart
+// from the value, so links from downstream futures reach it without touching T
+#[derive(Default)]
+struct Listeners<'t> {
+    consumers: usize,
+    // mirrors the callback queue while the future is pending
+    callbacks: usize,
+    // callbacks whose downstream future was dropped, they no longer count as consumers
+    detached: usize,
+    // the value is there, nobody is told about consumers leaving after that
+    done: bool,
+    on_consumers_gone: Vec<Box<dyn 't + FnOnce() + Send>>
+}
+
+impl<'t> Listeners<'t> {
+    fn abandoned(&self) -> bool {
+        self.consumers == 0 && self.callbacks == self.detached
+    }
+
+    // what to run, with the lock released, once the last listener of a pending future
+    // is gone
+    fn gone(&mut self) -> Option<Vec<Box<dyn 't + FnOnce() + Send>>> {
+        if !self.done && self.abandoned() {
+            Some(mem::take(&mut self.on_consumers_gone))
+        } else {
+            None
+        }
+    }
+}
+
 impl<'t, T> FutureState<'t, T> {
     fn new(value: T) -> FutureState<'t, T> {
         FutureState {
             value: ValSet(value),
             callbacks: Vec::new(),
-            ready_event: None
+            ready_event: None,
+            watchers: Vec::new(),
+            running: 0,
+            dispatch: None
         }
     }
 }
 
 impl<'t, T> Default for FutureState<'t, T> {
     fn default() -> FutureState<'t, T> {
+        #[cfg(feature = "metrics")]
+        metrics::created();
         FutureState {
             value: ValEmpty,
             callbacks: Vec::new(),
-            ready_event: None
+            ready_event: None,
+            watchers: Vec::new(),
+            running: 0,
+            dispatch: None
         }
     }
 }
 
+#[cfg(feature = "metrics")]
+impl<'t, T> Drop for FutureState<'t, T> {
+    fn drop(&mut self) {
+        if self.value.is_empty() {
+            metrics::abandoned();
+        }
+    }
+}
+
+// the listeners are locked after the state when both are needed
 #[derive(Default)]
 struct StateHolder<'t, T>
     where T: 't
 {
-    state: Arc<Spinlock<FutureState<'t, T>>>
+    state: Arc<Spinlock<FutureState<'t, T>>>,
+    listeners: Arc<Spinlock<Listeners<'t>>>
 }
 
 impl<'t, T> Clone for StateHolder<'t, T> {
     fn clone(&self) -> Self {
-        StateHolder{state: self.state.clone()}
+        StateHolder{state: self.state.clone(), listeners: self.listeners.clone()}
     }
 }
 
 impl<'t, T> StateHolder<'t, T> {
     fn preset(val: T) -> Self {
         StateHolder {
-            state: Arc::new(Spinlock::new(FutureState::new(val)))
+            state: Arc::new(Spinlock::new(FutureState::new(val))),
+            listeners: Arc::new(Spinlock::new(Listeners{done: true, ..Listeners::default()}))
         }
     }
 
     fn new() -> Self {
         StateHolder {
-            state: Arc::new(Spinlock::new(FutureState::default()))
+            state: Arc::new(Spinlock::new(FutureState::default())),
+            listeners: Arc::default()
         }
     }
 
+    fn listeners(&self) -> SpinlockGuard<'_, Listeners<'t>> {
+        self.listeners.lock().expect("spinlock poisoned")
+    }
+
     fn set(&self, value: T) {
-        let callbacks = {
+        self.complete(ValSet(value))
+    }
+
+    fn set_panic(&self, payload: PanicPayload) {
+        self.complete(ValPanicked(payload))
+    }
+
+    fn complete(&self, value: FutureValue<T>) {
+        if self.try_complete(value).is_err() {
+            panic!("double set on same future state");
+        }
+    }
+
+    fn try_complete(&self, value: FutureValue<T>) -> Result<(), FutureValue<T>> {
+        let panicked = matches!(value, ValPanicked(_));
+        let (callbacks, watchers, dispatch) = {
             let mut state = self.state.lock().expect("spinlock poisoned");
+            if !state.value.is_empty() {
+                return Err(value);
+            }
             state.value.put(value);
+            #[cfg(feature = "metrics")]
+            metrics::completed();
             let mut vec = Vec::new();
             mem::swap(&mut vec, &mut state.callbacks);
+            let mut listeners = self.listeners();
+            listeners.done = true;
+            listeners.callbacks = 0;
+            listeners.detached = 0;
+            drop(listeners);
+            state.running += vec.len();
             state.ready_event.as_ref().map(|ev| {ev.signal()});
-            vec
+            (vec, mem::take(&mut state.watchers), state.dispatch.filter(|_| callback_dispatch() == CallbackDispatch::Dispatcher))
         };
-        callbacks.into_iter().for_each(|f| {
-            Box::call_once(f, (self,));
+        self.drop_consumers_gone();
+        watchers.iter().for_each(|wake| wake());
+        instrument::emit(|instrument| instrument.future_set(panicked));
+        let callbacks = callbacks.into_iter().map(|(_, f)| f);
+        if let Some(dispatch) = dispatch {
+            if callbacks.len() > 0 {
+                dispatch(self, callbacks.collect());
+            }
+            return Ok(());
+        }
+        let mut first_panic = None;
+        callbacks.for_each(|f| {
+            #[cfg(feature = "metrics")]
+            metrics::callback_executed();
+            if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| self.run_callback(f))) {
+                if callback_panic_policy() == CallbackPanicPolicy::Abort {
+                    process::abort();
+                }
+                first_panic.get_or_insert(payload);
+            }
         });
+        if let Some(payload) = first_panic {
+            panic::resume_unwind(payload);
+        }
+        Ok(())
     }
 
     fn take(&self) -> T {
+        self.take_result().unwrap_or_else(|payload| panic::resume_unwind(payload))
+    }
+
+    fn take_result(&self) -> thread::Result<T> {
         self.wait();
         let mut state = self.state.lock();
         state.as_mut().expect("value already shared")
             .value.take()
     }
 
+    // None once the value is there
+    fn ready_event(&self) -> Option<Arc<Event>> {
+        match self.state.lock() {
+            None => {None},
+            Some(ref mut locked) if locked.value.is_empty() => {
+                let event = locked.ready_event
+                    .get_or_insert_with(|| Arc::new(Event::new()))
+                    .clone();
+                Some(event)
+            },
+            Some(_) => {None}
+        }
+    }
+
+    fn wait_deadline(&self, deadline: Instant) -> bool {
+        self.ready_event().is_none_or(|ev| ev.wait_deadline(deadline))
+    }
+
     fn wait(&self) {
-        let to_wait: Option<Arc<Event>> = {
-            match self.state.lock() {
-                None => {None},
-                Some(ref mut locked) => {
-                    if locked.ready_event.is_none() && locked.value.is_empty() {
-                        let event = Arc::new(Event::new());
-                        locked.ready_event = Option::Some(event.clone());
-                        Some(event)
-                    } else {
-                        None
-                    }
-                }
+        if let Some(ev) = self.ready_event() {
+            if instrument::installed() || cfg!(feature = "metrics") {
+                let started = Instant::now();
+                ev.wait();
+                let blocked = started.elapsed();
+                instrument::emit(|instrument| instrument.future_waited(blocked));
+                #[cfg(feature = "metrics")]
+                metrics::waited(blocked);
+            } else {
+                ev.wait();
             }
+        }
+    }
+
+    fn is_ready(&self) -> bool {
+        !self.state.lock().expect("spinlock poisoned").value.is_empty()
+    }
+
+    fn panicked(&self) -> bool {
+        matches!(self.state.lock().expect("spinlock poisoned").value, ValPanicked(_) | ValSharedPanic(_))
+    }
+
+    // None when the state is locked right now, Debug mustn't wait
+    fn peek_ready(&self) -> Option<bool> {
+        self.state.try_peek(|state| !state.value.is_empty())
+    }
+
+    fn debug(&self, name: &str, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut out = f.debug_struct(name);
+        match self.peek_ready() {
+            Some(ready) => out.field("ready", &ready),
+            None => out.field("ready", &Locked)
+        }.finish()
+    }
+
+    // nothing to register once the value is there, select checks is_ready after watching
+    fn watch(&self, wake: &Wake) {
+        let mut state = self.state.lock().expect("spinlock poisoned");
+        if state.value.is_empty() {
+            state.watchers.push(wake.clone());
+        }
+    }
+
+    fn unwatch(&self, wake: &Wake) {
+        self.state.lock().expect("spinlock poisoned").watchers.retain(|watcher| !StdArc::ptr_eq(watcher, wake));
+    }
+
+    fn pending_callbacks(&self) -> usize {
+        self.state.lock().expect("spinlock poisoned").callbacks.len()
+    }
+
+    // every blocked thread holds a clone of the event
+    fn waiters(&self) -> usize {
+        let state = self.state.lock().expect("spinlock poisoned");
+        state.ready_event.as_ref().map_or(0, |event| Arc::strong_count(event) - 1)
+    }
+
+    fn has_consumers(&self) -> bool {
+        !self.listeners().abandoned()
+    }
+
+    // succeeds for the last handle with no callback still to read the value, neither
+    // queued nor running
+    fn try_unshare(&self) -> bool {
+        let mut state = self.state.lock().expect("spinlock poisoned");
+        self.listeners().consumers == 1 && state.callbacks.is_empty() && state.running == 0 && state.value.unshare()
+    }
+
+    // f was counted as running when it left the queue
+    fn run_callback(&self, f: Callback<'t, T>) {
+        let _running = Release(|| self.state.lock().expect("spinlock poisoned").running -= 1);
+        f(self)
+    }
+
+    fn acquire_consumer(&self) {
+        self.listeners().consumers += 1;
+    }
+
+    fn release_consumer(&self) {
+        let gone = {
+            let mut listeners = self.listeners();
+            listeners.consumers -= 1;
+            listeners.gone()
         };
-        to_wait.map(|ev| {ev.wait()});
+        if let Some(gone) = gone {
+            if LEAK_DIAGNOSTICS.load(Ordering::Relaxed) {
+                eprintln!("future dropped while still pending\n{}", Backtrace::force_capture());
+            }
+            gone.into_iter().for_each(|f| f());
+        }
+    }
+
+    // once downstream is abandoned, the callback this holder has feeding it is
+    // detached, so dropping the end of a chain reaches the producers upstream
+    fn link<R>(&self, downstream: &StateHolder<'t, R>) {
+        let upstream = Upstream(self.listeners.clone());
+        downstream.on_consumers_gone(move || upstream.detach_callback());
+    }
+
+    // once the future is complete or can't be any more; links in there hold on to the
+    // futures upstream, so they are dropped outside of the lock
+    fn drop_consumers_gone(&self) {
+        let unneeded = mem::take(&mut self.listeners().on_consumers_gone);
+        drop(unneeded);
+    }
+
+    // runs right away if nobody is listening already
+    fn on_consumers_gone<Func>(&self, f: Func)
+        where Func: 't + FnOnce() + Send
+    {
+        let mut listeners = self.listeners();
+        if listeners.done {
+            return;
+        }
+        if listeners.abandoned() {
+            drop(listeners);
+            f();
+        } else {
+            listeners.on_consumers_gone.push(Box::new(f));
+        }
     }
 
     fn subscribe<Func>(&self, f: Func)
         where Func: 't + FnOnce(&StateHolder<'t, T>) -> () + Send
     {
-        let boxed = Box::new(f);
-        let mut guard = self.state.lock();
-        if guard.is_none() || !guard.as_ref().unwrap().value.is_empty() {
-            drop(guard);
-            Box::call_once(boxed, (self,));
+        self.subscribe_with_priority(Priority::Normal, f)
+    }
+
+    fn subscribe_with_priority<Func>(&self, priority: Priority, f: Func)
+        where Func: 't + FnOnce(&StateHolder<'t, T>) + Send
+    {
+        // the callback may run on whichever thread completes the future, so it takes
+        // the registering thread's context along
+        let context = Context::current();
+        let boxed: Callback<'t, T> = if context.is_empty() {
+            Box::new(f)
         } else {
-            guard.as_mut().unwrap().callbacks.push(boxed);
+            Box::new(move |holder: &StateHolder<'t, T>| context.run(|| f(holder)))
+        };
+        let mut guard = self.state.lock();
+        match guard.as_mut() {
+            Some(state) if state.value.is_empty() => {
+                let at = state.callbacks.iter().position(|&(queued, _)| queued < priority)
+                    .unwrap_or(state.callbacks.len());
+                state.callbacks.insert(at, (priority, boxed));
+                self.listeners().callbacks += 1;
+            },
+            Some(state) => {
+                state.running += 1;
+                drop(guard);
+                self.run_callback(boxed);
+            },
+            None => {
+                drop(guard);
+                boxed(self);
+            }
         }
     }
 }
 
-impl<'t, T> StateHolder<'t, T>
-    where T: Sync
-{
+impl<'t, T> StateHolder<'t, T> {
     fn get(&self) -> &T {
+        self.get_result().unwrap_or_else(|payload| panic::resume_unwind(payload))
+    }
+
+    fn try_get(&self) -> Option<&T> {
+        let ready = !self.state.lock().expect("spinlock poisoned").value.is_empty();
+        if ready {
+            Some(self.get())
+        } else {
+            None
+        }
+    }
+
+    // the Arc kept in the state isn't unshared while a handle or a running callback can
+    // still reach it, so it outlives &self
+    fn get_result(&self) -> thread::Result<&T> {
+        self.get_arc_result().map(|value| unsafe {&*Arc::as_ptr(&value)})
+    }
+
+    fn get_arc_result(&self) -> thread::Result<Arc<T>> {
         self.wait();
-        let state = self.state.share();
-        state.value.read()
+        let mut state = self.state.lock().expect("spinlock poisoned");
+        state.value.share()
+    }
+}
+
+// what a link keeps of the future upstream of it, nothing that could hold a T
+struct Upstream<'t>(Arc<Spinlock<Listeners<'t>>>);
+
+impl<'t> Upstream<'t> {
+    // one of the callbacks feeds a future nobody listens to any more
+    fn detach_callback(&self) {
+        let gone = {
+            let mut listeners = self.0.lock().expect("spinlock poisoned");
+            if listeners.done || listeners.detached == listeners.callbacks {
+                return;
+            }
+            listeners.detached += 1;
+            listeners.gone()
+        };
+        gone.into_iter().flatten().for_each(|f| f());
+    }
+}
+
+// every Future and SharedFuture handle keeps one of these alive
+struct Consumer<'t, T>
+    where T: 't
+{
+    holder: StateHolder<'t, T>
+}
+
+impl<'t, T> Consumer<'t, T> {
+    fn new(holder: &StateHolder<'t, T>) -> Consumer<'t, T> {
+        holder.acquire_consumer();
+        Consumer{holder: holder.clone()}
+    }
+}
+
+impl<'t, T> Drop for Consumer<'t, T> {
+    fn drop(&mut self) {
+        self.holder.release_consumer();
     }
 }
 
@@ -171,27 +653,179 @@ pub struct Promise<'t, T>
     holder: StateHolder<'t, T>
 }
 
+impl<T: 'static + Send> Promise<'static, T> {
+    // with CallbackDispatch::Dispatcher set, callbacks of this future run on the
+    // dispatcher thread instead of in the thread calling set()
+    pub fn new_static() -> (Promise<'static, T>, Future<'static, T>) {
+        let (promise, future) = Promise::new();
+        promise.holder.state.lock().expect("spinlock poisoned").dispatch = Some(dispatch::<T>);
+        (promise, future)
+    }
+}
+
 impl<'t, T> Promise<'t, T> {
     pub fn new() -> (Promise<'t, T>, Future<'t, T>) {
         let holder = StateHolder::new();
-        (Promise{holder:holder.clone()}, Future{holder:holder})
+        (Promise{holder:holder.clone()}, Future::with_holder(holder))
     }
 
     pub fn set(self: Promise<'t, T>, value: T) {
         self.holder.set(value)
     }
+
+    // unlike set, losing a race against another producer hands the value back
+    pub fn try_set(&self, value: T) -> Result<(), T> {
+        self.holder.try_complete(ValSet(value)).map_err(|value| {
+            match value {
+                ValSet(value) => value,
+                _ => unreachable!()
+            }
+        })
+    }
+
+    // f is skipped when every future and callback attached to this promise is gone
+    pub fn set_with<Func>(self: Promise<'t, T>, f: Func)
+        where Func: FnOnce() -> T
+    {
+        if self.holder.has_consumers() {
+            self.set(f());
+        }
+    }
+
+    pub fn complete_with(self: Promise<'t, T>, future: Future<'t, T>)
+        where T: Send
+    {
+        future.holder.link(&self.holder);
+        future.holder.subscribe(move |holder| {
+            self.set_result(holder.take_result());
+        });
+    }
+
+    // f runs once every future handle is dropped and no callback is waiting,
+    // so the producer can abandon work nobody will look at; apply/then chains whose
+    // last future was dropped don't count as waiting
+    pub fn on_consumers_gone<Func>(&self, f: Func)
+        where Func: 't + FnOnce() + Send
+    {
+        self.holder.on_consumers_gone(f)
+    }
+
+    pub fn is_pending(&self) -> bool {
+        !self.holder.is_ready()
+    }
+
+    pub(crate) fn has_consumers(&self) -> bool {
+        self.holder.has_consumers()
+    }
+
+    // how many continuations are attached, for diagnostics and tests
+    pub fn pending_callbacks(&self) -> usize {
+        self.holder.pending_callbacks()
+    }
+
+    // how many threads are blocked in wait or take right now
+    pub fn waiters(&self) -> usize {
+        self.holder.waiters()
+    }
+
+    // for C callback apis taking a user data pointer, which is then completed
+    // through complete_raw or turned back with from_raw
+    pub fn into_raw(self) -> *mut c_void {
+        Box::into_raw(Box::new(self)) as *mut c_void
+    }
+
+    /// # Safety
+    /// raw must come from into_raw of a Promise<'t, T> and is consumed
+    pub unsafe fn from_raw(raw: *mut c_void) -> Promise<'t, T> {
+        *Box::from_raw(raw as *mut Promise<'t, T>)
+    }
+
+    // take() on the future re-raises the panic
+    pub fn set_panic(self: Promise<'t, T>, payload: PanicPayload) {
+        self.holder.set_panic(payload)
+    }
+
+    pub fn set_result(self: Promise<'t, T>, result: thread::Result<T>) {
+        match result {
+            Ok(value) => self.set(value),
+            Err(payload) => self.set_panic(payload)
+        }
+    }
 }
 
-pub struct Future<'t, T>
+// nothing completes the future after an unset promise is gone, so what waits for its
+// consumers to leave is of no use any more
+impl<'t, T> Drop for Promise<'t, T> {
+    fn drop(&mut self) {
+        self.holder.drop_consumers_gone();
+    }
+}
+
+impl<'t, T> fmt::Debug for Promise<'t, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.holder.debug("Promise", f)
+    }
+}
+
+// completion shim to hand to C as a callback, e.g. complete_raw::<i32> for a
+// void (*)(void *, int). A panicking continuation aborts, it can't unwind into C
+/// # Safety
+/// promise must come from Promise::<'static, T>::into_raw and is consumed
+pub unsafe extern "C" fn complete_raw<T: 'static>(promise: *mut c_void, value: T) {
+    Promise::<'static, T>::from_raw(promise).set(value)
+}
+
+// cloned to every producer of a hedged computation; the first set wins and the
+// later ones are dropped instead of panicking
+pub struct RacePromise<'t, T>
     where T: 't
 {
     holder: StateHolder<'t, T>
 }
 
+impl<'t, T> Clone for RacePromise<'t, T> {
+    fn clone(&self) -> Self {
+        RacePromise{holder: self.holder.clone()}
+    }
+}
+
+impl<'t, T> RacePromise<'t, T> {
+    pub fn new() -> (RacePromise<'t, T>, Future<'t, T>) {
+        let holder = StateHolder::new();
+        (RacePromise{holder: holder.clone()}, Future::with_holder(holder))
+    }
+
+    // true if this set won the race
+    pub fn set(&self, value: T) -> bool {
+        self.holder.try_complete(ValSet(value)).is_ok()
+    }
+
+    // lets producers skip work once another one has won
+    pub fn is_pending(&self) -> bool {
+        !self.holder.is_ready()
+    }
+}
+
+pub struct Future<'t, T>
+    where T: 't
+{
+    holder: StateHolder<'t, T>,
+    _consumer: Consumer<'t, T>
+}
+
+// continuations are boxed inside the shared state already, so futures built from
+// different closures have the same type and can sit in one collection as they are
+pub type BoxFuture<'t, T> = Future<'t, T>;
+
 impl<'t, T> Future<'t, T> {
     pub fn new(val: T) -> Future<'t, T> {
+        Future::with_holder(StateHolder::preset(val))
+    }
+
+    fn with_holder(holder: StateHolder<'t, T>) -> Future<'t, T> {
         Future {
-            holder: StateHolder::preset(val)
+            _consumer: Consumer::new(&holder),
+            holder
         }
     }
 
@@ -204,81 +838,473 @@ impl<'t, T> Future<'t, T> {
               Func: 't + FnOnce(T) -> R + Send
     {
         let (promise, future) = Promise::new();
+        self.holder.link(&future.holder);
+        self.holder.subscribe(move |holder| {
+            promise.set_result(holder.take_result().map(f));
+        });
+        future
+    }
+
+    pub fn then<F, Func>(self, f: Func) -> Future<'t, F::Output>
+        where Func: 't + FnOnce(T) -> F + Send,
+              F: IntoFuture<'t>,
+              F::Output: Send
+    {
+        let (promise, future) = Promise::new();
+        self.holder.link(&future.holder);
+        self.holder.subscribe(move |holder| {
+            match holder.take_result() {
+                Ok(value) => {promise.complete_with(f(value).into_future());},
+                Err(payload) => {promise.set_panic(payload);}
+            }
+        });
+        future
+    }
+
+    // f sees a panic of the stages before as Err, so this link can recover from it
+    pub fn apply_catch<R, Func>(self, f: Func) -> Future<'t, R>
+        where R: 't + Send,
+              Func: 't + FnOnce(Result<T, PanicPayload>) -> R + Send
+    {
+        let (promise, future) = Promise::new();
+        self.holder.link(&future.holder);
+        self.holder.subscribe(move |holder| {
+            let result = holder.take_result();
+            promise.set_result(panic::catch_unwind(AssertUnwindSafe(|| f(result))));
+        });
+        future
+    }
+
+    pub fn then_catch<F, Func>(self, f: Func) -> Future<'t, F::Output>
+        where Func: 't + FnOnce(Result<T, PanicPayload>) -> F + Send,
+              F: IntoFuture<'t>,
+              F::Output: Send
+    {
+        let (promise, future) = Promise::new();
+        self.holder.link(&future.holder);
         self.holder.subscribe(move |holder| {
-            promise.set(f(holder.take()));
+            let result = holder.take_result();
+            match panic::catch_unwind(AssertUnwindSafe(|| f(result).into_future())) {
+                Ok(next) => {promise.complete_with(next);},
+                Err(payload) => {promise.set_panic(payload);}
+            }
         });
         future
     }
 
-    pub fn then<R, Func>(self, f: Func) -> Future<'t, R>
-        where Func: 't + FnOnce(T) -> Future<'t, R> + Send,
-              R: 't + Send
+    pub fn left_future<U>(self) -> Future<'t, Either<T, U>>
+        where T: Send,
+              U: 't + Send
+    {
+        self.apply(Either::Left)
+    }
+
+    pub fn right_future<U>(self) -> Future<'t, Either<U, T>>
+        where T: Send,
+              U: 't + Send
+    {
+        self.apply(Either::Right)
+    }
+
+    pub fn inspect<Func>(self, f: Func) -> Future<'t, T>
+        where T: Send,
+              Func: 't + FnOnce(&T) + Send
+    {
+        self.apply(move |value| {
+            f(&value);
+            value
+        })
+    }
+
+    pub fn map2<U, R, Func>(self, other: Future<'t, U>, f: Func) -> Future<'t, R>
+        where T: Send,
+              U: 't + Send,
+              R: 't + Send,
+              Func: 't + FnOnce(T, U) -> R + Send
     {
         let (promise, future) = Promise::new();
+        self.holder.link(&future.holder);
         self.holder.subscribe(move |holder| {
-            f(holder.take()).holder.subscribe(move |holder| {
-                promise.set(holder.take());
-            });
+            match holder.take_result() {
+                Ok(left) => {
+                    other.holder.subscribe(move |holder| {
+                        promise.set_result(holder.take_result().map(|right| f(left, right)));
+                    });
+                },
+                Err(payload) => {promise.set_panic(payload);}
+            }
         });
         future
     }
 
+    // f runs in the thread completing the future, or right away if it's already set
+    pub fn on_complete<Func>(self, f: Func)
+        where Func: 't + FnOnce(T) + Send
+    {
+        self.holder.subscribe(move |holder| f(holder.take()));
+    }
+
+    // the value is handed to executor, so whatever is chained after via runs there
+    // instead of inline in the thread calling set()
+    pub fn via<E>(self, executor: E) -> Future<'t, T>
+        where T: Send,
+              E: 't + Executor<'t> + Send
+    {
+        let (promise, future) = Promise::new();
+        self.holder.link(&future.holder);
+        self.holder.subscribe(move |holder| {
+            let result = holder.take_result();
+            executor.execute(Box::new(move || promise.set_result(result)));
+        });
+        future
+    }
+
+    // a panicked future sends nothing, the receiver sees the channel close once
+    // every other sender is gone
+    pub fn send_to(self, sender: Sender<T>)
+        where T: Send
+    {
+        self.holder.subscribe(move |holder| {
+            if let Ok(value) = holder.take_result() {
+                let _ = sender.send(value);
+            }
+        });
+    }
+
     pub fn wait(&self) {
         self.holder.wait()
     }
-}
 
-impl<'t, T: Sync> Future<'t, T> {
+    // true if the value (or a panic) is there by the deadline, several waits can share
+    // one deadline without recomputing what is left of it
+    pub fn wait_deadline(&self, deadline: Instant) -> bool {
+        self.holder.wait_deadline(deadline)
+    }
+
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        self.wait_deadline(Instant::now() + timeout)
+    }
+
+    // f runs if this future is dropped before completion with nothing else waiting on it
+    pub fn on_drop<Func>(&self, f: Func)
+        where Func: 't + FnOnce() + Send
+    {
+        self.holder.on_consumers_gone(f)
+    }
+
+    pub fn fuse(self) -> FusedFuture<'t, T> {
+        FusedFuture{future: Some(self)}
+    }
+
     pub fn share(self) -> SharedFuture<'t, T> {
         SharedFuture {
-            holder: self.holder
+            _consumer: Consumer::new(&self.holder),
+            holder: self.holder,
+            _marker: PhantomData
+        }
+    }
+}
+
+pub type Job<'t> = Box<dyn 't + FnOnce() + Send>;
+
+pub trait Executor<'t> {
+    fn execute(&self, job: Job<'t>);
+}
+
+// any closure taking the job works as an executor, e.g. one pushing into a queue
+impl<'t, Func: Fn(Job<'t>)> Executor<'t> for Func {
+    fn execute(&self, job: Job<'t>) {
+        self(job)
+    }
+}
+
+impl<'t, T> From<T> for Future<'t, T> {
+    fn from(val: T) -> Future<'t, T> {
+        Future::new(val)
+    }
+}
+
+// what a then() continuation may return. A blanket impl over every T would overlap
+// with the one for Future itself, so plain values come wrapped in Ready
+pub trait IntoFuture<'t> {
+    type Output: 't;
+
+    fn into_future(self) -> Future<'t, Self::Output>;
+}
+
+impl<'t, T: 't> IntoFuture<'t> for Future<'t, T> {
+    type Output = T;
+
+    fn into_future(self) -> Future<'t, T> {
+        self
+    }
+}
+
+// a value that is already there, e.g. then(|x| Ready(x + 1))
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct Ready<T>(pub T);
+
+impl<'t, T: 't> IntoFuture<'t> for Ready<T> {
+    type Output = T;
+
+    fn into_future(self) -> Future<'t, T> {
+        Future::new(self.0)
+    }
+}
+
+// the registration stays until the future completes, so a wake may come late
+impl<'t, T> Waitable for Future<'t, T> {
+    fn is_ready(&self) -> bool {
+        self.holder.is_ready()
+    }
+
+    fn watch(&self, wake: &Wake) {
+        self.holder.watch(wake)
+    }
+
+    fn unwatch(&self, wake: &Wake) {
+        self.holder.unwatch(wake)
+    }
+}
+
+impl<'t, T> Waitable for SharedFuture<'t, T> {
+    fn is_ready(&self) -> bool {
+        self.holder.is_ready()
+    }
+
+    fn watch(&self, wake: &Wake) {
+        self.holder.watch(wake)
+    }
+
+    fn unwatch(&self, wake: &Wake) {
+        self.holder.unwatch(wake)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Either<L, R> {
+    Left(L),
+    Right(R)
+}
+
+impl<L, R> Either<L, R> {
+    pub fn is_left(&self) -> bool {
+        matches!(*self, Either::Left(_))
+    }
+
+    pub fn is_right(&self) -> bool {
+        !self.is_left()
+    }
+
+    pub fn left(self) -> Option<L> {
+        match self {
+            Either::Left(value) => Some(value),
+            Either::Right(_) => None
+        }
+    }
+
+    pub fn right(self) -> Option<R> {
+        match self {
+            Either::Left(_) => None,
+            Either::Right(value) => Some(value)
         }
     }
 }
 
+// can be kept in a struct and taken from by reference, later calls see None
+pub struct FusedFuture<'t, T>
+    where T: 't
+{
+    future: Option<Future<'t, T>>
+}
+
+impl<'t, T> FusedFuture<'t, T> {
+    pub fn take(&mut self) -> Option<T> {
+        self.future.take().map(|future| future.take())
+    }
+
+    pub fn wait(&self) {
+        if let Some(ref future) = self.future {
+            future.wait();
+        }
+    }
+
+    pub fn wait_deadline(&self, deadline: Instant) -> bool {
+        self.future.as_ref().is_none_or(|future| future.wait_deadline(deadline))
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.future.as_ref().is_some_and(|future| future.holder.is_ready())
+    }
+
+    pub fn is_terminated(&self) -> bool {
+        self.future.is_none()
+    }
+}
+
+impl<'t, T: 't + Send> Future<'t, Future<'t, T>> {
+    pub fn flatten(self) -> Future<'t, T> {
+        self.then(|inner| inner)
+    }
+}
+
+impl<'t, T, E> Future<'t, Result<T, E>>
+    where T: 't + Send,
+          E: 't
+{
+    pub fn recover<Func>(self, f: Func) -> Future<'t, T>
+        where Func: 't + FnOnce(E) -> T + Send
+    {
+        self.apply(move |result| result.unwrap_or_else(f))
+    }
+
+    pub fn unwrap_or(self, default: T) -> Future<'t, T> {
+        self.apply(move |result| result.unwrap_or(default))
+    }
+}
+
+impl<'t, T> fmt::Debug for Future<'t, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.holder.debug("Future", f)
+    }
+}
+
+// the value is kept behind an Arc, so clones may only cross threads when T: Send + Sync
 pub struct SharedFuture<'t, T>
-    where T: 't + Sync
+    where T: 't
 {
-    holder: StateHolder<'t, T>
+    holder: StateHolder<'t, T>,
+    _consumer: Consumer<'t, T>,
+    _marker: PhantomData<Arc<T>>
 }
 
-impl<'t, T: Sync> Clone for SharedFuture<'t, T> {
+impl<'t, T> fmt::Debug for SharedFuture<'t, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.holder.debug("SharedFuture", f)
+    }
+}
+
+impl<'t, T> Clone for SharedFuture<'t, T> {
     fn clone(&self) -> Self {
-        SharedFuture{holder: self.holder.clone()}
+        SharedFuture {
+            holder: self.holder.clone(),
+            _consumer: Consumer::new(&self.holder),
+            _marker: PhantomData
+        }
     }
 }
 
-impl<'t, T: 't + Sync> SharedFuture<'t, T> {
+impl<'t, T: 't> SharedFuture<'t, T> {
     pub fn get(&self) -> &T {
         self.holder.get()
     }
 
+    // never blocks, a panicked producer is re-raised just like in get()
+    pub fn try_get(&self) -> Option<&T> {
+        self.holder.try_get()
+    }
+
+    pub fn get_arc(&self) -> Arc<T> {
+        self.holder.get_arc_result().unwrap_or_else(|payload| panic::resume_unwind(payload))
+    }
+
+    pub fn wait(&self) {
+        self.holder.wait()
+    }
+
+    pub fn wait_deadline(&self, deadline: Instant) -> bool {
+        self.holder.wait_deadline(deadline)
+    }
+
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        self.wait_deadline(Instant::now() + timeout)
+    }
+
+    // f runs with a handle to this future once its producer has panicked; it never sees
+    // a value, so unlike subscribe this needs no T: Sync
+    pub(crate) fn on_panic<Func>(&self, f: Func)
+        where Func: 't + FnOnce(&SharedFuture<'t, T>) + Send
+    {
+        self.holder.subscribe(move |holder| {
+            if holder.panicked() {
+                f(&SharedFuture {
+                    holder: holder.clone(),
+                    _consumer: Consumer::new(holder),
+                    _marker: PhantomData
+                });
+            }
+        });
+    }
+
+    // whether both handles are clones of one future
+    pub(crate) fn same_future(&self, other: &SharedFuture<'t, T>) -> bool {
+        Arc::ptr_eq(&self.holder.state, &other.holder.state)
+    }
+
+    // fails while other clones, pending callbacks or Arcs from get_arc are around
+    pub fn try_unshare(self) -> Result<Future<'t, T>, SharedFuture<'t, T>> {
+        if !self.holder.try_unshare() {
+            return Err(self);
+        }
+        // the future counts as a consumer before this handle lets go
+        Ok(Future::with_holder(self.holder.clone()))
+    }
+}
+
+// callbacks run in the completing thread, so they need T: Sync to see &T
+impl<'t, T: 't + Sync> SharedFuture<'t, T> {
     pub fn apply<R, Func>(&self, f: Func) -> Future<'t, R>
         where R: 't + Send,
               Func: 't + FnOnce(&T) -> R + Send
     {
         let (promise, future) = Promise::new();
         self.holder.subscribe(move |holder| {
-            promise.set(f(holder.get()));
+            promise.set_result(holder.get_result().map(f));
         });
         future
     }
 
-    pub fn then<R, Func>(&self, f: Func) -> Future<'t, R>
-        where Func: 't + FnOnce(&T) -> Future<'t, R> + Send,
-              R: 't + Send
+    pub fn then<F, Func>(&self, f: Func) -> Future<'t, F::Output>
+        where Func: 't + FnOnce(&T) -> F + Send,
+              F: IntoFuture<'t>,
+              F::Output: Send
     {
         let (promise, future) = Promise::new();
         self.holder.subscribe(move |holder| {
-            f(holder.get()).holder.subscribe(move |holder| {
-                promise.set(holder.take());
-            });
+            match holder.get_result() {
+                Ok(value) => {promise.complete_with(f(value).into_future());},
+                Err(payload) => {promise.set_panic(payload);}
+            }
         });
         future
     }
 
-    pub fn wait(&self) {
-        self.holder.wait()
+    // callbacks run in the order they were subscribed
+    pub fn subscribe<Func>(&self, f: Func)
+        where Func: 't + FnOnce(&T) + Send
+    {
+        self.holder.subscribe(move |holder| f(holder.get()));
+    }
+
+    // f runs on executor once the value is there, so slow listeners don't hold up the
+    // others or the thread calling set()
+    pub fn subscribe_on<E, Func>(&self, executor: E, f: Func)
+        where T: Send,
+              E: 't + Executor<'t> + Send,
+              Func: 't + FnOnce(&T) + Send
+    {
+        let shared = self.clone();
+        self.holder.subscribe(move |_| {
+            executor.execute(Box::new(move || f(shared.get())));
+        });
+    }
+
+    // runs before every callback of lower priority, whenever it was subscribed;
+    // continuations like apply and then are Normal
+    pub fn subscribe_with_priority<Func>(&self, priority: Priority, f: Func)
+        where Func: 't + FnOnce(&T) + Send
+    {
+        self.holder.subscribe_with_priority(priority, move |holder| f(holder.get()));
     }
 }
 
@@ -303,7 +1329,7 @@ impl<F> Drop for Waiter<F>
     where F: FnOnce() -> ()
 {
     fn drop(self: &mut Waiter<F>) {
-        Box::call_once(self.on_destroy.take().unwrap(), ())
+        (self.on_destroy.take().unwrap())()
     }
 }
 
@@ -342,3 +1368,279 @@ pub fn wait_any<'i, 't, T, I>(i: I) -> Future<'t, ()>
     });
     future
 }
+
+struct TryJoin<'t, T, E>
+    where T: 't, E: 't
+{
+    values: Vec<Option<T>>,
+    remaining: usize,
+    promise: Option<Promise<'t, Result<Vec<T>, E>>>
+}
+
+// resolves with the first error right away, without waiting for the rest
+pub fn try_join_all<'t, T, E, I>(i: I) -> Future<'t, Result<Vec<T>, E>>
+    where I: IntoIterator<Item = Future<'t, Result<T, E>>>,
+          T: 't + Send,
+          E: 't + Send
+{
+    let futures: Vec<_> = i.into_iter().collect();
+    let (promise, future) = Promise::new();
+    if futures.is_empty() {
+        promise.set(Ok(Vec::new()));
+        return future;
+    }
+    let join = Arc::new(Mutex::new(TryJoin {
+        values: futures.iter().map(|_| None).collect(),
+        remaining: futures.len(),
+        promise: Some(promise)
+    }));
+    futures.into_iter().enumerate().for_each(|(idx, f)| {
+        let join = join.clone();
+        f.holder.subscribe(move |holder| {
+            // set outside of the lock, continuations run right there
+            let (promise, result) = {
+                let mut join = join.lock().unwrap();
+                if join.promise.is_none() {
+                    return;
+                }
+                let result = match holder.take_result() {
+                    Ok(Ok(value)) => {
+                        join.values[idx] = Some(value);
+                        join.remaining -= 1;
+                        if join.remaining > 0 {
+                            return;
+                        }
+                        Ok(Ok(join.values.iter_mut().map(|v| v.take().unwrap()).collect()))
+                    },
+                    Ok(Err(err)) => Ok(Err(err)),
+                    Err(payload) => Err(payload)
+                };
+                (join.promise.take().unwrap(), result)
+            };
+            promise.set_result(result);
+        });
+    });
+    future
+}
+
+struct Traverse<'t, I, R, Func>
+    where R: 't
+{
+    items: I,
+    f: Func,
+    limit: usize,
+    results: Vec<Option<R>>,
+    in_flight: usize,
+    exhausted: bool,
+    // only one thread pulls items at a time, the rest just record their results
+    pumping: bool,
+    promise: Option<Promise<'t, Vec<R>>>
+}
+
+fn pump<'t, I, R, Func>(traverse: Arc<Mutex<Traverse<'t, I, R, Func>>>)
+    where I: 't + Iterator + Send,
+          R: 't + Send,
+          Func: 't + FnMut(I::Item) -> Future<'t, R> + Send
+{
+    {
+        let mut state = traverse.lock().unwrap();
+        if state.pumping {
+            return;
+        }
+        state.pumping = true;
+    }
+    loop {
+        let (idx, future) = {
+            let mut state = traverse.lock().unwrap();
+            if state.promise.is_none() || state.in_flight >= state.limit {
+                state.pumping = false;
+                return;
+            }
+            let item = if state.exhausted { None } else { state.items.next() };
+            match item {
+                None => {
+                    state.exhausted = true;
+                    state.pumping = false;
+                    if state.in_flight == 0 {
+                        let values = state.results.iter_mut().map(|v| v.take().unwrap()).collect();
+                        let promise = state.promise.take().unwrap();
+                        drop(state);
+                        promise.set(values);
+                    }
+                    return;
+                },
+                Some(item) => {
+                    state.results.push(None);
+                    state.in_flight += 1;
+                    (state.results.len() - 1, (state.f)(item))
+                }
+            }
+        };
+        let traverse = traverse.clone();
+        future.holder.subscribe(move |holder| {
+            let failed = {
+                let mut state = traverse.lock().unwrap();
+                state.in_flight -= 1;
+                match holder.take_result() {
+                    Ok(value) => {
+                        state.results[idx] = Some(value);
+                        None
+                    },
+                    Err(payload) => state.promise.take().map(|promise| (promise, payload))
+                }
+            };
+            match failed {
+                Some((promise, payload)) => {promise.set_panic(payload);},
+                None => {pump(traverse);}
+            }
+        });
+    }
+}
+
+pub fn traverse<'t, I, R, Func>(i: I, f: Func) -> Future<'t, Vec<R>>
+    where I: IntoIterator,
+          I::IntoIter: 't + Send,
+          R: 't + Send,
+          Func: 't + FnMut(I::Item) -> Future<'t, R> + Send
+{
+    traverse_limited(i, usize::MAX, f)
+}
+
+// at most limit futures returned by f are pending at any moment
+pub fn traverse_limited<'t, I, R, Func>(i: I, limit: usize, f: Func) -> Future<'t, Vec<R>>
+    where I: IntoIterator,
+          I::IntoIter: 't + Send,
+          R: 't + Send,
+          Func: 't + FnMut(I::Item) -> Future<'t, R> + Send
+{
+    assert!(limit > 0, "traverse needs a positive concurrency limit");
+    let (promise, future) = Promise::new();
+    pump(Arc::new(Mutex::new(Traverse {
+        items: i.into_iter(),
+        f,
+        limit,
+        results: Vec::new(),
+        in_flight: 0,
+        exhausted: false,
+        pumping: false,
+        promise: Some(promise)
+    })));
+    future
+}
+
+// built with collect(), resolves with every value in input order
+pub struct JoinAll<'t, T>
+    where T: 't
+{
+    future: Future<'t, Vec<T>>
+}
+
+impl<'t, T: 't + Send> FromIterator<Future<'t, T>> for JoinAll<'t, T> {
+    fn from_iter<I: IntoIterator<Item = Future<'t, T>>>(i: I) -> JoinAll<'t, T> {
+        let futures: Vec<_> = i.into_iter().collect();
+        JoinAll{future: traverse(futures, |f| f)}
+    }
+}
+
+impl<'t, T> JoinAll<'t, T> {
+    pub fn take(self) -> Vec<T> {
+        self.future.take()
+    }
+
+    pub fn wait(&self) {
+        self.future.wait()
+    }
+}
+
+impl<'t, T: 't> IntoFuture<'t> for JoinAll<'t, T> {
+    type Output = Vec<T>;
+
+    fn into_future(self) -> Future<'t, Vec<T>> {
+        self.future
+    }
+}
+
+impl<'t, T> From<JoinAll<'t, T>> for Future<'t, Vec<T>> {
+    fn from(join: JoinAll<'t, T>) -> Future<'t, Vec<T>> {
+        join.future
+    }
+}
+
+struct FirstOk<'t, T, E>
+    where T: 't, E: 't
+{
+    errors: Vec<Option<E>>,
+    remaining: usize,
+    promise: Option<Promise<'t, Result<T, Vec<E>>>>
+}
+
+// errors are reported in input order, and only if every future failed
+pub fn first_ok<'t, T, E, I>(i: I) -> Future<'t, Result<T, Vec<E>>>
+    where I: IntoIterator<Item = Future<'t, Result<T, E>>>,
+          T: 't + Send,
+          E: 't + Send
+{
+    let futures: Vec<_> = i.into_iter().collect();
+    let (promise, future) = Promise::new();
+    if futures.is_empty() {
+        promise.set(Err(Vec::new()));
+        return future;
+    }
+    let first = Arc::new(Mutex::new(FirstOk {
+        errors: futures.iter().map(|_| None).collect(),
+        remaining: futures.len(),
+        promise: Some(promise)
+    }));
+    futures.into_iter().enumerate().for_each(|(idx, f)| {
+        let first = first.clone();
+        f.holder.subscribe(move |holder| {
+            // set outside of the lock, as in try_join_all
+            let (promise, result) = {
+                let mut first = first.lock().unwrap();
+                if first.promise.is_none() {
+                    return;
+                }
+                let result = match holder.take_result() {
+                    Ok(Ok(value)) => Ok(Ok(value)),
+                    Ok(Err(err)) => {
+                        first.errors[idx] = Some(err);
+                        first.remaining -= 1;
+                        if first.remaining > 0 {
+                            return;
+                        }
+                        Ok(Err(first.errors.iter_mut().map(|e| e.take().unwrap()).collect()))
+                    },
+                    Err(payload) => Err(payload)
+                };
+                (first.promise.take().unwrap(), result)
+            };
+            promise.set_result(result);
+        });
+    });
+    future
+}
+
+// resolves with whichever future completes first, the other value is dropped
+pub fn select<'t, L, R>(left: Future<'t, L>, right: Future<'t, R>) -> Future<'t, Either<L, R>>
+    where L: 't + Send,
+          R: 't + Send
+{
+    let (promise, future) = Promise::new();
+    let promise = Arc::new(Mutex::new(Some(promise)));
+    {
+        let promise = promise.clone();
+        left.holder.subscribe(move |holder| {
+            let promise = promise.lock().unwrap().take();
+            if let Some(promise) = promise {
+                promise.set_result(holder.take_result().map(Either::Left));
+            }
+        });
+    }
+    right.holder.subscribe(move |holder| {
+        let promise = promise.lock().unwrap().take();
+        if let Some(promise) = promise {
+            promise.set_result(holder.take_result().map(Either::Right));
+        }
+    });
+    future
+}
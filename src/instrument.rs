@@ -0,0 +1,78 @@
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct TaskId(u64);
+
+static NEXT_TASK_ID: AtomicU64 = AtomicU64::new(0);
+
+impl TaskId {
+    fn next() -> TaskId {
+        TaskId(NEXT_TASK_ID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
+}
+
+// every hook is a no-op by default, so a subscriber only overrides what it forwards
+pub trait Instrument: Send + Sync {
+    fn task_spawned(&self, _task: TaskId) {}
+
+    fn task_started(&self, _task: TaskId) {}
+
+    fn task_finished(&self, _task: TaskId) {}
+
+    fn future_set(&self, _panicked: bool) {}
+
+    fn future_waited(&self, _blocked: Duration) {}
+
+    fn lock_contended(&self) {}
+}
+
+static INSTRUMENT: OnceLock<Box<dyn Instrument>> = OnceLock::new();
+
+// a subscriber can be installed once per process, later calls get theirs back
+pub fn install(instrument: Box<dyn Instrument>) -> Result<(), Box<dyn Instrument>> {
+    INSTRUMENT.set(instrument)
+}
+
+pub(crate) fn installed() -> bool {
+    INSTRUMENT.get().is_some()
+}
+
+#[inline]
+pub(crate) fn emit<Func: FnOnce(&dyn Instrument)>(f: Func) {
+    if let Some(instrument) = INSTRUMENT.get() {
+        f(&**instrument);
+    }
+}
+
+// task ids are only handed out while someone is listening
+pub(crate) fn spawned() -> Option<TaskId> {
+    INSTRUMENT.get().map(|instrument| {
+        let task = TaskId::next();
+        instrument.task_spawned(task);
+        task
+    })
+}
+
+// reports the task finished when dropped, including while unwinding
+pub(crate) struct Running(Option<TaskId>);
+
+pub(crate) fn started(task: Option<TaskId>) -> Running {
+    if let Some(task) = task {
+        emit(|instrument| instrument.task_started(task));
+    }
+    Running(task)
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        if let Some(task) = self.0 {
+            emit(|instrument| instrument.task_finished(task));
+        }
+    }
+}
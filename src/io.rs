@@ -0,0 +1,35 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::OnceLock;
+use future::Future;
+use scope;
+use threads::ThreadConfig;
+
+static BLOCKING: OnceLock<ThreadConfig> = OnceLock::new();
+
+// runs f on a thread of its own, named "blocking-<n>" so blocking calls are easy to
+// tell apart from compute work in thread dumps
+#[track_caller]
+pub fn offload<Func, R>(f: Func) -> Future<'static, R>
+    where Func: 'static + Send + FnOnce() -> R,
+          R: 'static + Send
+{
+    let config = BLOCKING.get_or_init(|| ThreadConfig::new().name_prefix("blocking"));
+    scope::spawn_async_on(config, f)
+}
+
+#[track_caller]
+pub fn read_file<P: AsRef<Path>>(path: P) -> Future<'static, io::Result<Vec<u8>>> {
+    let path = path.as_ref().to_owned();
+    offload(move || fs::read(path))
+}
+
+#[track_caller]
+pub fn write_file<P, C>(path: P, contents: C) -> Future<'static, io::Result<()>>
+    where P: AsRef<Path>,
+          C: 'static + Send + AsRef<[u8]>
+{
+    let path = path.as_ref().to_owned();
+    offload(move || fs::write(path, contents))
+}
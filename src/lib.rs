@@ -1,11 +1,67 @@
-#![feature(fn_traits)]
+#![cfg_attr(not(feature = "std"), no_std)]
 
+#[cfg(loom)]
+extern crate loom;
+#[cfg(feature = "std")]
+extern crate core;
+#[cfg(feature = "alloc")]
+extern crate alloc;
+#[cfg(feature = "lock-api")]
+extern crate lock_api;
+
+#[cfg(feature = "std")]
 pub mod future;
-pub mod async;
+#[cfg(feature = "std")]
+pub mod scope;
+#[cfg(feature = "std")]
+pub use scope::yield_now_if_contended;
 pub mod event;
+#[cfg(feature = "alloc")]
 pub mod atom;
 pub mod spinlock;
+pub mod backoff;
+#[cfg(all(feature = "lock-api", not(loom)))]
+pub mod lockapi;
+mod sync;
+#[cfg(feature = "std")]
+pub mod context;
+#[cfg(feature = "std")]
+pub mod instrument;
+#[cfg(feature = "std")]
+pub mod tasks;
+#[cfg(feature = "std")]
+pub mod threads;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+#[cfg(feature = "deadlock-detection")]
+pub mod lockdebug;
+#[cfg(feature = "std")]
+pub mod io;
+#[cfg(feature = "std")]
+pub mod channel;
+#[cfg(feature = "std")]
+pub mod parallel;
+#[cfg(feature = "std")]
+pub mod memo;
+#[cfg(feature = "std")]
+pub mod actor;
+#[cfg(feature = "std")]
+pub mod pipeline;
+#[cfg(feature = "std")]
+pub mod ratelimit;
+#[cfg(feature = "std")]
+pub mod semaphore;
+#[cfg(feature = "std")]
+pub mod monitor;
+#[cfg(feature = "std")]
+pub mod breaker;
+#[cfg(feature = "std")]
+pub mod counter;
+#[cfg(feature = "std")]
+pub mod striped;
+#[cfg(feature = "sim")]
+pub mod sim;
 
-#[cfg(test)]
+#[cfg(all(test, feature = "std"))]
 mod tests;
 
@@ -0,0 +1,189 @@
+// the spin locks' algorithms on bare state, for code written against lock_api; these
+// need a const INIT, so they use core atomics directly and skip deadlock detection
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use lock_api::{GuardSend, RawMutex, RawRwLock, RawRwLockDowngrade, RawRwLockUpgrade, RawRwLockUpgradeDowngrade};
+use backoff::Backoff;
+use spinlock::report_contention;
+
+pub type Spinlock<T> = lock_api::Mutex<RawSpinlock, T>;
+pub type SpinlockGuard<'t, T> = lock_api::MutexGuard<'t, RawSpinlock, T>;
+pub type SpinRWLock<T> = lock_api::RwLock<RawSpinRWLock, T>;
+pub type SpinReadGuard<'t, T> = lock_api::RwLockReadGuard<'t, RawSpinRWLock, T>;
+pub type SpinWriteGuard<'t, T> = lock_api::RwLockWriteGuard<'t, RawSpinRWLock, T>;
+pub type SpinUpgradableGuard<'t, T> = lock_api::RwLockUpgradableReadGuard<'t, RawSpinRWLock, T>;
+
+pub struct RawSpinlock {
+    locked: AtomicBool
+}
+
+unsafe impl RawMutex for RawSpinlock {
+    const INIT: RawSpinlock = RawSpinlock{locked: AtomicBool::new(false)};
+
+    type GuardMarker = GuardSend;
+
+    fn lock(&self) {
+        let mut contended = false;
+        let mut backoff = Backoff::new();
+        while !self.try_lock() {
+            report_contention(&mut contended);
+            backoff.snooze();
+        }
+    }
+
+    fn try_lock(&self) -> bool {
+        self.locked.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed).is_ok()
+    }
+
+    unsafe fn unlock(&self) {
+        self.locked.store(false, Ordering::Release);
+    }
+
+    fn is_locked(&self) -> bool {
+        self.locked.load(Ordering::Relaxed)
+    }
+}
+
+// same protocol as spinlock::SpinRWLock: the upgrader flag is held by the writer or the
+// upgradable reader, and the upgradable reader is counted among the readers
+pub struct RawSpinRWLock {
+    readers: AtomicUsize,
+    write: AtomicBool,
+    upgrader: AtomicBool
+}
+
+impl RawSpinRWLock {
+    fn wait_for_readers(&self) {
+        let mut contended = false;
+        let mut backoff = Backoff::new();
+        while self.readers.load(Ordering::SeqCst) != 0 {
+            report_contention(&mut contended);
+            backoff.snooze();
+        }
+    }
+}
+
+unsafe impl RawRwLock for RawSpinRWLock {
+    const INIT: RawSpinRWLock = RawSpinRWLock {
+        readers: AtomicUsize::new(0),
+        write: AtomicBool::new(false),
+        upgrader: AtomicBool::new(false)
+    };
+
+    type GuardMarker = GuardSend;
+
+    fn lock_shared(&self) {
+        let mut contended = false;
+        let mut backoff = Backoff::new();
+        while !self.try_lock_shared() {
+            report_contention(&mut contended);
+            backoff.snooze();
+        }
+    }
+
+    fn try_lock_shared(&self) -> bool {
+        self.readers.fetch_add(1, Ordering::SeqCst);
+        if self.write.load(Ordering::SeqCst) {
+            self.readers.fetch_sub(1, Ordering::SeqCst);
+            false
+        } else {
+            true
+        }
+    }
+
+    unsafe fn unlock_shared(&self) {
+        self.readers.fetch_sub(1, Ordering::Release);
+    }
+
+    fn lock_exclusive(&self) {
+        self.lock_upgradable();
+        unsafe { self.upgrade() }
+    }
+
+    fn try_lock_exclusive(&self) -> bool {
+        if !self.try_lock_upgradable() {
+            return false;
+        }
+        unsafe {
+            if self.try_upgrade() {
+                true
+            } else {
+                self.unlock_upgradable();
+                false
+            }
+        }
+    }
+
+    unsafe fn unlock_exclusive(&self) {
+        self.write.store(false, Ordering::Release);
+        self.upgrader.store(false, Ordering::Release);
+    }
+
+    fn is_locked(&self) -> bool {
+        self.upgrader.load(Ordering::Relaxed) || self.readers.load(Ordering::Relaxed) != 0
+    }
+
+    fn is_locked_exclusive(&self) -> bool {
+        self.write.load(Ordering::Relaxed)
+    }
+}
+
+unsafe impl RawRwLockDowngrade for RawSpinRWLock {
+    unsafe fn downgrade(&self) {
+        self.readers.fetch_add(1, Ordering::SeqCst);
+        self.unlock_exclusive();
+    }
+}
+
+unsafe impl RawRwLockUpgrade for RawSpinRWLock {
+    fn lock_upgradable(&self) {
+        let mut contended = false;
+        let mut backoff = Backoff::new();
+        while !self.try_lock_upgradable() {
+            report_contention(&mut contended);
+            backoff.snooze();
+        }
+    }
+
+    fn try_lock_upgradable(&self) -> bool {
+        if self.upgrader.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed).is_err() {
+            return false;
+        }
+        // holding the upgrader leaves no writer to wait for
+        self.readers.fetch_add(1, Ordering::SeqCst);
+        true
+    }
+
+    unsafe fn unlock_upgradable(&self) {
+        self.readers.fetch_sub(1, Ordering::Release);
+        self.upgrader.store(false, Ordering::Release);
+    }
+
+    unsafe fn upgrade(&self) {
+        self.write.store(true, Ordering::SeqCst);
+        self.readers.fetch_sub(1, Ordering::SeqCst);
+        self.wait_for_readers();
+    }
+
+    // readers arriving after the flag is raised back off, so only the upgrader itself
+    // may be left; a reader still backing off makes this fail spuriously
+    unsafe fn try_upgrade(&self) -> bool {
+        self.write.store(true, Ordering::SeqCst);
+        if self.readers.load(Ordering::SeqCst) != 1 {
+            self.write.store(false, Ordering::Release);
+            return false;
+        }
+        self.readers.fetch_sub(1, Ordering::SeqCst);
+        true
+    }
+}
+
+unsafe impl RawRwLockUpgradeDowngrade for RawSpinRWLock {
+    unsafe fn downgrade_upgradable(&self) {
+        self.upgrader.store(false, Ordering::Release);
+    }
+
+    unsafe fn downgrade_to_upgradable(&self) {
+        self.readers.fetch_add(1, Ordering::SeqCst);
+        self.write.store(false, Ordering::Release);
+    }
+}
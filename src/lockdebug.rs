@@ -0,0 +1,192 @@
+use std::sync::{Mutex, MutexGuard};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::cell::RefCell;
+use std::thread::{self, ThreadId};
+use std::time::{Duration, Instant};
+use std::backtrace::Backtrace;
+use std::collections::HashMap;
+
+static LONG_HOLD_MICROS: AtomicU64 = AtomicU64::new(100_000);
+static NEXT_LOCK_ID: AtomicUsize = AtomicUsize::new(0);
+
+// edges[a][b] is where some thread first took b while holding a
+type OrderGraph = HashMap<usize, HashMap<usize, Backtrace>>;
+
+static ORDER: Mutex<Option<OrderGraph>> = Mutex::new(None);
+
+thread_local! {
+    static HELD: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+}
+
+// guards held longer than this are reported on release
+pub fn set_long_hold_threshold(threshold: Duration) {
+    LONG_HOLD_MICROS.store(threshold.as_micros() as u64, Ordering::Relaxed);
+}
+
+fn long_hold_threshold() -> Duration {
+    Duration::from_micros(LONG_HOLD_MICROS.load(Ordering::Relaxed))
+}
+
+struct Holder {
+    thread: ThreadId,
+    since: Instant,
+    site: Backtrace
+}
+
+impl Holder {
+    fn current() -> Holder {
+        Holder {
+            thread: thread::current().id(),
+            since: Instant::now(),
+            site: Backtrace::force_capture()
+        }
+    }
+
+    fn release(self) {
+        let held = self.since.elapsed();
+        if held > long_hold_threshold() {
+            eprintln!("lock held for {:?} by {:?}, acquired at:\n{}", held, self.thread, self.site);
+        }
+    }
+}
+
+// the self-deadlock panics fire while these are held
+fn relock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn order_path(graph: &OrderGraph, from: usize, to: usize) -> Option<&Backtrace> {
+    let mut stack: Vec<(usize, &Backtrace)> = graph.get(&from)?.iter().map(|(&next, site)| (next, site)).collect();
+    let mut seen = vec![from];
+    while let Some((node, first)) = stack.pop() {
+        if node == to {
+            return Some(first);
+        }
+        if seen.contains(&node) {
+            continue;
+        }
+        seen.push(node);
+        if let Some(edges) = graph.get(&node) {
+            stack.extend(edges.keys().map(|&next| (next, first)));
+        }
+    }
+    None
+}
+
+// panics if taking `lock` now contradicts an order some thread used before
+fn check_order(lock: usize) {
+    let held = HELD.with(|held| held.borrow().clone());
+    if held.is_empty() {
+        return;
+    }
+    let mut order = relock(&ORDER);
+    let graph = order.get_or_insert_with(HashMap::new);
+    for &outer in held.iter().filter(|&&outer| outer != lock) {
+        if let Some(site) = order_path(graph, lock, outer) {
+            let reversed = format!("{}", site);
+            drop(order);
+            panic!("lock order inversion: acquiring a lock here:\n{}\nwhile the opposite order was established at:\n{}",
+                   Backtrace::force_capture(), reversed);
+        }
+        graph.entry(outer).or_default().entry(lock).or_insert_with(Backtrace::force_capture);
+    }
+}
+
+fn push_held(lock: usize) {
+    HELD.with(|held| held.borrow_mut().push(lock));
+}
+
+fn pop_held(lock: usize) {
+    let _ = HELD.try_with(|held| {
+        let mut held = held.borrow_mut();
+        if let Some(pos) = held.iter().rposition(|&id| id == lock) {
+            held.remove(pos);
+        }
+    });
+}
+
+// who currently holds a lock, kept next to every spinlock when the feature is on
+pub(crate) struct Ownership {
+    id: usize,
+    writer: Mutex<Option<Holder>>,
+    readers: Mutex<HashMap<ThreadId, Vec<Holder>>>
+}
+
+impl Default for Ownership {
+    fn default() -> Ownership {
+        Ownership {
+            id: NEXT_LOCK_ID.fetch_add(1, Ordering::Relaxed),
+            writer: Mutex::default(),
+            readers: Mutex::default()
+        }
+    }
+}
+
+impl Drop for Ownership {
+    fn drop(&mut self) {
+        if let Some(ref mut graph) = *relock(&ORDER) {
+            graph.remove(&self.id);
+            graph.values_mut().for_each(|edges| {edges.remove(&self.id);});
+        }
+    }
+}
+
+impl Ownership {
+    fn check_writer(&self) {
+        let writer = relock(&self.writer);
+        if let Some(ref holder) = *writer {
+            if holder.thread == thread::current().id() {
+                panic!("self-deadlock: lock is already held by this thread, acquired at:\n{}", holder.site);
+            }
+        }
+    }
+
+    pub fn before_read(&self) {
+        self.check_writer();
+        check_order(self.id);
+    }
+
+    pub fn before_write(&self) {
+        self.check_writer();
+        let readers = relock(&self.readers);
+        if let Some(holder) = readers.get(&thread::current().id()).and_then(|held| held.last()) {
+            panic!("self-deadlock: write lock requested while holding a read guard acquired at:\n{}", holder.site);
+        }
+        drop(readers);
+        check_order(self.id);
+    }
+
+    pub fn write_locked(&self) {
+        *relock(&self.writer) = Some(Holder::current());
+        push_held(self.id);
+    }
+
+    pub fn write_unlocked(&self) {
+        pop_held(self.id);
+        if let Some(holder) = relock(&self.writer).take() {
+            holder.release();
+        }
+    }
+
+    pub fn read_locked(&self) {
+        relock(&self.readers)
+            .entry(thread::current().id())
+            .or_default()
+            .push(Holder::current());
+        push_held(self.id);
+    }
+
+    pub fn read_unlocked(&self) {
+        pop_held(self.id);
+        let mut readers = relock(&self.readers);
+        let id = thread::current().id();
+        let holder = readers.get_mut(&id).and_then(|held| held.pop());
+        if readers.get(&id).is_some_and(|held| held.is_empty()) {
+            readers.remove(&id);
+        }
+        drop(readers);
+        if let Some(holder) = holder {
+            holder.release();
+        }
+    }
+}
@@ -0,0 +1,103 @@
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
+use std::panic::{self, AssertUnwindSafe};
+use future::{Future, SharedFuture, Promise};
+
+const DEFAULT_SHARDS: usize = 16;
+
+type Shard<'t, K, T> = Mutex<HashMap<K, SharedFuture<'t, T>>>;
+
+pub struct MemoCache<'t, K, T>
+    where T: 't
+{
+    // shared with the callbacks that forget panicked computations
+    shards: Arc<[Shard<'t, K, T>]>
+}
+
+// the eviction callbacks may run on whichever thread completes a computation
+impl<'t, K, T> MemoCache<'t, K, T>
+    where K: 't + Hash + Eq + Send,
+          T: 't + Sync + Send
+{
+    pub fn new() -> Self {
+        MemoCache::with_shards(DEFAULT_SHARDS)
+    }
+
+    pub fn with_shards(count: usize) -> Self {
+        assert!(count > 0, "memo cache needs at least one shard");
+        MemoCache {
+            shards: (0..count).map(|_| Mutex::new(HashMap::new())).collect()
+        }
+    }
+
+    // f is called at most once per key, outside of the shard lock. If f or the future
+    // it returns panics the key is forgotten, so the next call computes it again; callers
+    // already waiting for it see the panic, and one raised by f goes on in this thread
+    pub fn get_or_compute<Func>(&self, key: K, f: Func) -> SharedFuture<'t, T>
+        where Func: FnOnce() -> Future<'t, T>
+    {
+        let index = self.shard_index(&key);
+        let (promise, shared) = {
+            let mut entries = self.shards[index].lock().unwrap();
+            if let Some(future) = entries.get(&key) {
+                return future.clone();
+            }
+            let (promise, future) = Promise::new();
+            let shared = future.share();
+            entries.insert(key, shared.clone());
+            (promise, shared)
+        };
+        let shards = Arc::downgrade(&self.shards);
+        shared.on_panic(move |panicked| {
+            if let Some(shards) = shards.upgrade() {
+                // the key may have been removed and computed anew meanwhile
+                shards[index].lock().unwrap().retain(|_, future| !future.same_future(panicked));
+            }
+        });
+        match panic::catch_unwind(AssertUnwindSafe(f)) {
+            Ok(future) => {promise.complete_with(future);},
+            Err(payload) => {
+                promise.set_panic(Box::new("memoized computation panicked"));
+                panic::resume_unwind(payload);
+            }
+        }
+        shared
+    }
+
+    pub fn get(&self, key: &K) -> Option<SharedFuture<'t, T>> {
+        self.shard(key).lock().unwrap().get(key).cloned()
+    }
+
+    pub fn remove(&self, key: &K) -> Option<SharedFuture<'t, T>> {
+        self.shard(key).lock().unwrap().remove(key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|shard| shard.lock().unwrap().len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn shard(&self, key: &K) -> &Shard<'t, K, T> {
+        &self.shards[self.shard_index(key)]
+    }
+
+    fn shard_index(&self, key: &K) -> usize {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        (hasher.finish() as usize) % self.shards.len()
+    }
+}
+
+impl<'t, K, T> Default for MemoCache<'t, K, T>
+    where K: 't + Hash + Eq + Send,
+          T: 't + Sync + Send
+{
+    fn default() -> Self {
+        MemoCache::new()
+    }
+}
@@ -0,0 +1,53 @@
+use std::sync::atomic::{AtomicUsize, AtomicU64, Ordering};
+use std::time::Duration;
+
+static PENDING: AtomicUsize = AtomicUsize::new(0);
+static COMPLETED: AtomicU64 = AtomicU64::new(0);
+static CALLBACKS: AtomicU64 = AtomicU64::new(0);
+static WAITS: AtomicU64 = AtomicU64::new(0);
+static WAITED_NANOS: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Snapshot {
+    // created through a promise and neither completed nor dropped yet
+    pub pending: usize,
+    pub completed: u64,
+    pub callbacks_executed: u64,
+    // only waits that actually had to block are counted
+    pub waits: u64,
+    pub average_wait: Duration
+}
+
+pub fn snapshot() -> Snapshot {
+    let waits = WAITS.load(Ordering::Relaxed);
+    let waited = WAITED_NANOS.load(Ordering::Relaxed);
+    Snapshot {
+        pending: PENDING.load(Ordering::Relaxed),
+        completed: COMPLETED.load(Ordering::Relaxed),
+        callbacks_executed: CALLBACKS.load(Ordering::Relaxed),
+        waits,
+        average_wait: Duration::from_nanos(waited.checked_div(waits).unwrap_or(0))
+    }
+}
+
+pub(crate) fn created() {
+    PENDING.fetch_add(1, Ordering::Relaxed);
+}
+
+pub(crate) fn completed() {
+    PENDING.fetch_sub(1, Ordering::Relaxed);
+    COMPLETED.fetch_add(1, Ordering::Relaxed);
+}
+
+pub(crate) fn abandoned() {
+    PENDING.fetch_sub(1, Ordering::Relaxed);
+}
+
+pub(crate) fn callback_executed() {
+    CALLBACKS.fetch_add(1, Ordering::Relaxed);
+}
+
+pub(crate) fn waited(blocked: Duration) {
+    WAITS.fetch_add(1, Ordering::Relaxed);
+    WAITED_NANOS.fetch_add(blocked.as_nanos() as u64, Ordering::Relaxed);
+}
@@ -0,0 +1,53 @@
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::time::Duration;
+
+// a value together with the condvar signaling changes to it
+#[derive(Default)]
+pub struct Monitor<T> {
+    value: Mutex<T>,
+    changed: Condvar
+}
+
+impl<T> Monitor<T> {
+    pub fn new(value: T) -> Monitor<T> {
+        Monitor {
+            value: Mutex::new(value),
+            changed: Condvar::new()
+        }
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        self.value.lock().unwrap()
+    }
+
+    // blocks while cond holds, spurious wakeups are handled here
+    pub fn wait_while<'a, Func>(&self, guard: MutexGuard<'a, T>, cond: Func) -> MutexGuard<'a, T>
+        where Func: FnMut(&mut T) -> bool
+    {
+        self.changed.wait_while(guard, cond).unwrap()
+    }
+
+    // the bool is true if cond still held when timeout ran out
+    pub fn wait_timeout_while<'a, Func>(&self, guard: MutexGuard<'a, T>, timeout: Duration, cond: Func)
+        -> (MutexGuard<'a, T>, bool)
+        where Func: FnMut(&mut T) -> bool
+    {
+        let (guard, result) = self.changed.wait_timeout_while(guard, timeout, cond).unwrap();
+        (guard, result.timed_out())
+    }
+
+    pub fn notify_one(&self) {
+        self.changed.notify_one();
+    }
+
+    pub fn notify_all(&self) {
+        self.changed.notify_all();
+    }
+
+    // changes the value under the lock and wakes every waiter
+    pub fn update<R, Func: FnOnce(&mut T) -> R>(&self, f: Func) -> R {
+        let result = f(&mut self.lock());
+        self.notify_all();
+        result
+    }
+}
@@ -0,0 +1,162 @@
+use std::thread;
+use std::vec;
+use std::ops::Range;
+use std::sync::Mutex;
+use scope::enter;
+
+// below this many elements forking costs more than sorting in place
+pub const DEFAULT_SORT_CUTOFF: usize = 4096;
+
+// there is no shared pool, every call spreads its work over at most this many threads
+fn workers() -> usize {
+    thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+pub trait ParMap: Iterator + Sized {
+    // runs f on every item in parallel and yields the results in input order once
+    // all of them are done; a panic in f is re-raised here
+    fn par_map<R, Func>(self, f: Func) -> vec::IntoIter<R>
+        where Self::Item: Send,
+              R: Send,
+              Func: Fn(Self::Item) -> R + Sync
+    {
+        let f = &f;
+        let results: Vec<R> = enter(move |scope| {
+            scope.set_max_concurrency(Some(workers()));
+            let futures: Vec<_> = self.map(|item| scope.spawn_async(move || f(item))).collect();
+            futures.into_iter().map(|future| future.take()).collect()
+        });
+        results.into_iter()
+    }
+}
+
+impl<I: Iterator> ParMap for I {}
+
+pub fn sort<T: Ord + Send>(v: &mut [T]) {
+    sort_with_cutoff(v, DEFAULT_SORT_CUTOFF)
+}
+
+// fork-join quicksort, slices shorter than cutoff are sorted sequentially; like
+// sort_unstable it may reorder equal elements
+pub fn sort_with_cutoff<T: Ord + Send>(v: &mut [T], cutoff: usize) {
+    let forks = workers().next_power_of_two().trailing_zeros() as usize + 1;
+    quicksort(v, cutoff.max(2), forks);
+}
+
+fn quicksort<T: Ord + Send>(v: &mut [T], cutoff: usize, forks: usize) {
+    if v.len() <= cutoff || forks == 0 {
+        v.sort_unstable();
+        return;
+    }
+    let pivot = partition(v);
+    let (left, right) = v.split_at_mut(pivot);
+    let right = &mut right[1..];
+    enter(move |scope| {
+        scope.spawn(move || quicksort(left, cutoff, forks - 1));
+        quicksort(right, cutoff, forks - 1);
+    });
+}
+
+// items are split into contiguous runs, each folded on its own thread starting from
+// identity(), and the partial results are reduced in input order, so both operators
+// only need to be associative
+pub fn fold<I, A, Identity, Fold, Reduce>(items: I, identity: Identity, fold_op: Fold, reduce_op: Reduce) -> A
+    where I: IntoIterator,
+          I::Item: Send,
+          A: Send,
+          Identity: Fn() -> A + Sync,
+          Fold: Fn(A, I::Item) -> A + Sync,
+          Reduce: Fn(A, A) -> A
+{
+    let mut items: Vec<_> = items.into_iter().collect();
+    let count = workers().min(items.len()).max(1);
+    let mut runs = Vec::with_capacity(count);
+    (1..count).rev().for_each(|idx| runs.push(items.split_off(idx * items.len() / (idx + 1))));
+    runs.push(items);
+    runs.reverse();
+    let (identity, fold_op) = (&identity, &fold_op);
+    let partials: Vec<A> = enter(move |scope| {
+        let futures: Vec<_> = runs.into_iter()
+            .map(|run| scope.spawn_async(move || run.into_iter().fold(identity(), fold_op)))
+            .collect();
+        futures.into_iter().map(|future| future.take()).collect()
+    });
+    partials.into_iter().reduce(reduce_op).unwrap()
+}
+
+// each worker starts with an equal share and takes small chunks off its front; one that
+// runs dry steals the back half of the largest share left, so a few slow items don't
+// leave the others idle
+pub fn for_each_chunk<T, Func>(v: &mut [T], f: Func)
+    where T: Send,
+          Func: Fn(&mut [T]) + Sync
+{
+    let len = v.len();
+    if len == 0 {
+        return;
+    }
+    let count = workers().min(len);
+    let grain = (len / (count * 32)).max(1);
+    let shares = Mutex::new((0..count).map(|idx| idx * len / count..(idx + 1) * len / count).collect::<Vec<_>>());
+    let slice = RawSlice(v.as_mut_ptr());
+    let (shares, slice, f) = (&shares, &slice, &f);
+    enter(move |scope| {
+        (1..count).for_each(|idx| scope.spawn(move || run_chunks(idx, shares, grain, slice, f)));
+        run_chunks(0, shares, grain, slice, f);
+    });
+}
+
+struct RawSlice<T>(*mut T);
+
+// every chunk handed out is disjoint from the others
+unsafe impl<T: Send> Sync for RawSlice<T> {}
+
+fn run_chunks<T, Func>(idx: usize, shares: &Mutex<Vec<Range<usize>>>, grain: usize, slice: &RawSlice<T>, f: &Func)
+    where Func: Fn(&mut [T])
+{
+    while let Some(chunk) = next_chunk(idx, shares, grain) {
+        f(unsafe { ::std::slice::from_raw_parts_mut(slice.0.add(chunk.start), chunk.len()) });
+    }
+}
+
+fn next_chunk(idx: usize, shares: &Mutex<Vec<Range<usize>>>, grain: usize) -> Option<Range<usize>> {
+    let mut shares = shares.lock().unwrap();
+    if shares[idx].is_empty() {
+        let victim = (0..shares.len()).max_by_key(|&other| shares[other].len())?;
+        let left = shares[victim].len();
+        if left == 0 {
+            return None;
+        }
+        let mid = shares[victim].start + left / 2;
+        shares[idx] = mid..shares[victim].end;
+        shares[victim].end = mid;
+    }
+    let share = &mut shares[idx];
+    let chunk = share.start..(share.start + grain).min(share.end);
+    share.start = chunk.end;
+    Some(chunk)
+}
+
+// median of three moved to the end, returns where it lands
+fn partition<T: Ord>(v: &mut [T]) -> usize {
+    let last = v.len() - 1;
+    let mid = v.len() / 2;
+    if v[mid] < v[0] {
+        v.swap(mid, 0);
+    }
+    if v[last] < v[0] {
+        v.swap(last, 0);
+    }
+    if v[mid] < v[last] {
+        v.swap(mid, last);
+    }
+    let mut store = 0;
+    for idx in 0..last {
+        if v[idx] < v[last] {
+            v.swap(idx, store);
+            store += 1;
+        }
+    }
+    v.swap(store, last);
+    store
+}
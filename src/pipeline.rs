@@ -0,0 +1,110 @@
+use std::sync::mpsc::{sync_channel, SyncSender, Receiver, TrySendError};
+use std::sync::{Arc, Mutex};
+use future::{Future, wait_all};
+use scope::spawn_async;
+
+pub struct Builder<In, Cur> {
+    input: SyncSender<In>,
+    output: Receiver<Cur>,
+    capacity: usize,
+    workers: Vec<Future<'static, ()>>
+}
+
+impl<In: 'static + Send> Builder<In, In> {
+    // capacity bounds every queue between stages, producers block when it is full
+    pub fn new(capacity: usize) -> Builder<In, In> {
+        let (input, output) = sync_channel(capacity);
+        Builder {
+            input,
+            output,
+            capacity,
+            workers: Vec::new()
+        }
+    }
+}
+
+impl<In, Cur> Builder<In, Cur>
+    where In: 'static + Send,
+          Cur: 'static + Send
+{
+    pub fn stage<Next, Func>(self, workers: usize, f: Func) -> Builder<In, Next>
+        where Next: 'static + Send,
+              Func: 'static + Send + Sync + Fn(Cur) -> Next
+    {
+        let (sender, receiver) = sync_channel(self.capacity);
+        let capacity = self.capacity;
+        let (input, workers) = self.spawn_workers(workers, move |item| {
+            sender.send(f(item)).is_ok()
+        });
+        Builder {
+            input,
+            output: receiver,
+            capacity,
+            workers
+        }
+    }
+
+    pub fn sink<Func>(self, workers: usize, f: Func) -> Pipeline<In>
+        where Func: 'static + Send + Sync + Fn(Cur)
+    {
+        let (input, workers) = self.spawn_workers(workers, move |item| {
+            f(item);
+            true
+        });
+        Pipeline {
+            input,
+            done: wait_all(workers.iter())
+        }
+    }
+
+    // each worker stops once its input is drained or the next stage has gone away
+    fn spawn_workers<Func>(self, count: usize, f: Func) -> (SyncSender<In>, Vec<Future<'static, ()>>)
+        where Func: 'static + Send + Sync + Fn(Cur) -> bool
+    {
+        assert!(count > 0, "pipeline stage needs at least one worker");
+        let Builder {input, output, mut workers, ..} = self;
+        let output = Arc::new(Mutex::new(output));
+        let f = Arc::new(f);
+        for _ in 0..count {
+            let output = output.clone();
+            let f = f.clone();
+            workers.push(spawn_async(move || {
+                loop {
+                    let item = output.lock().unwrap().recv();
+                    match item {
+                        Ok(item) => {
+                            if !f(item) {
+                                break;
+                            }
+                        },
+                        Err(_) => {break;}
+                    }
+                }
+            }));
+        }
+        (input, workers)
+    }
+}
+
+pub struct Pipeline<In> {
+    input: SyncSender<In>,
+    done: Future<'static, ()>
+}
+
+impl<In: 'static + Send> Pipeline<In> {
+    // blocks while the first queue is full, fails if every worker has stopped
+    pub fn push(&self, item: In) -> Result<(), In> {
+        self.input.send(item).map_err(|err| err.0)
+    }
+
+    pub fn try_push(&self, item: In) -> Result<(), TrySendError<In>> {
+        self.input.try_send(item)
+    }
+
+    // closes the input, the future resolves once every queued item is processed
+    pub fn finish(self) -> Future<'static, ()> {
+        let Pipeline {input, done} = self;
+        drop(input);
+        done
+    }
+}
@@ -0,0 +1,81 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use std::thread;
+use future::Future;
+use scope::spawn_async;
+
+struct Bucket {
+    tokens: f64,
+    refilled: Instant
+}
+
+struct Limits {
+    rate: f64,
+    burst: f64
+}
+
+impl Bucket {
+    fn refill(&mut self, limits: &Limits) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled);
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * limits.rate).min(limits.burst);
+        self.refilled = now;
+    }
+
+    // returns how long to sleep before a token becomes available
+    fn take(&mut self, limits: &Limits) -> Option<Duration> {
+        self.refill(limits);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            None
+        } else {
+            Some(Duration::from_secs_f64((1.0 - self.tokens) / limits.rate))
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RateLimiter {
+    bucket: Arc<Mutex<Bucket>>,
+    limits: Arc<Limits>
+}
+
+impl RateLimiter {
+    // starts full, so up to burst permits can be taken at once
+    pub fn new(permits_per_second: f64, burst: usize) -> RateLimiter {
+        assert!(permits_per_second > 0.0, "rate must be positive");
+        assert!(burst > 0, "burst must allow at least one permit");
+        RateLimiter {
+            bucket: Arc::new(Mutex::new(Bucket {
+                tokens: burst as f64,
+                refilled: Instant::now()
+            })),
+            limits: Arc::new(Limits {
+                rate: permits_per_second,
+                burst: burst as f64
+            })
+        }
+    }
+
+    pub fn try_acquire(&self) -> bool {
+        self.bucket.lock().unwrap().take(&self.limits).is_none()
+    }
+
+    pub fn acquire(&self) {
+        loop {
+            let delay = self.bucket.lock().unwrap().take(&self.limits);
+            match delay {
+                None => {return;},
+                Some(delay) => {thread::sleep(delay);}
+            }
+        }
+    }
+
+    pub fn acquire_future(&self) -> Future<'static, ()> {
+        if self.try_acquire() {
+            return Future::new(());
+        }
+        let limiter = self.clone();
+        spawn_async(move || limiter.acquire())
+    }
+}
@@ -0,0 +1,536 @@
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::collections::VecDeque;
+use std::cell::{Cell, RefCell};
+use std::marker::PhantomData;
+use future::{Future, Promise, PanicPayload, traverse};
+// for spawns it only matters while they are queued behind the concurrency limit
+pub use future::Priority;
+use std::mem::{self, ManuallyDrop};
+use std::panic::{self, AssertUnwindSafe};
+use std::panic::Location;
+use std::thread;
+use instrument;
+use tasks;
+use threads::{self, ThreadConfig};
+
+type Deferred<'scope> = Box<dyn 'scope + FnOnce()>;
+type PanicHandler<'scope> = Arc<dyn 'scope + Fn(&PanickedTask, PanicPayload) + Send + Sync>;
+
+struct Task<'scope> {
+    run: Box<dyn 'scope + FnOnce() + Send>,
+    spawned_at: &'static Location<'static>,
+    priority: Priority
+}
+
+#[derive(Clone, Debug)]
+pub struct PanickedTask {
+    pub spawned_at: &'static Location<'static>,
+    pub thread_name: Option<String>
+}
+
+// what worker threads share with the scope; spawns over the concurrency limit wait
+// here for a running worker to pick them up
+#[derive(Default)]
+struct Workers<'scope> {
+    tasks: VecDeque<Task<'scope>>,
+    running: usize,
+    limit: Option<usize>,
+    panic_handler: Option<PanicHandler<'scope>>
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum DeferOrder {
+    // in the order they were deferred, which joins threads in spawn order
+    #[default]
+    Fifo,
+    // most recent first, like stack unwinding
+    Lifo
+}
+
+struct CancelState {
+    canceled: AtomicBool,
+    parent: Option<Cancellation>
+}
+
+// a scope's cancellation flag that spawned closures can carry, a child scope
+// counts as canceled as soon as any of its ancestors is
+#[derive(Clone)]
+pub struct Cancellation {
+    state: Arc<CancelState>
+}
+
+impl Cancellation {
+    fn new(parent: Option<Cancellation>) -> Cancellation {
+        Cancellation {
+            state: Arc::new(CancelState{canceled: AtomicBool::new(false), parent})
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.state.canceled.store(true, Ordering::Release);
+    }
+
+    pub fn is_canceled(&self) -> bool {
+        self.state.canceled.load(Ordering::Acquire)
+            || self.state.parent.as_ref().is_some_and(Cancellation::is_canceled)
+    }
+}
+
+// 'scope is the lifetime of the scope itself and 'env of whatever spawned closures borrow,
+// both invariant so a scope can't be passed off as one that outlives its threads
+pub struct DeferScope<'scope, 'env: 'scope> {
+    // always drained by run_deferred before the scope goes away, and keeping it
+    // out of drop glue lets enter borrow the scope for exactly 'scope
+    to_run: ManuallyDrop<Mutex<Vec<Deferred<'scope>>>>,
+    workers: ManuallyDrop<Mutex<Workers<'scope>>>,
+    config: Option<ThreadConfig>,
+    order: Cell<DeferOrder>,
+    cancellation: Cancellation,
+    scope: PhantomData<&'scope mut &'scope ()>,
+    env: PhantomData<&'env mut &'env ()>
+}
+
+impl<'scope, 'env> DeferScope<'scope, 'env> {
+    fn new(config: Option<ThreadConfig>, order: DeferOrder, cancellation: Cancellation) -> DeferScope<'scope, 'env> {
+        DeferScope {
+            to_run: ManuallyDrop::new(Mutex::new(Vec::new())),
+            workers: ManuallyDrop::new(Mutex::new(Workers::default())),
+            config,
+            order: Cell::new(order),
+            cancellation,
+            scope: PhantomData,
+            env: PhantomData
+        }
+    }
+
+    pub fn defer<Func: 'scope + FnOnce()>(&'scope self, f: Func) {
+        self.to_run.lock().unwrap().push(Box::new(f));
+    }
+
+    // a nested scope with this one's thread config and defer order; whatever it
+    // spawns or defers is done before child returns
+    pub fn child<'child_env, Func, R>(&self, f: Func) -> R
+        where Func: for<'child> FnOnce(&'child DeferScope<'child, 'child_env>) -> R
+    {
+        let cancellation = Cancellation::new(Some(self.cancellation.clone()));
+        resume_first(run_scope(self.config.clone(), self.order.get(), cancellation, f))
+    }
+
+    // only sets a flag, spawned work has to check it and bail out on its own
+    pub fn cancel(&self) {
+        self.cancellation.cancel();
+    }
+
+    pub fn is_canceled(&self) -> bool {
+        self.cancellation.is_canceled()
+    }
+
+    // for spawned closures, which can't borrow the scope itself
+    pub fn cancellation(&self) -> Cancellation {
+        self.cancellation.clone()
+    }
+
+    // at most n threads run this scope's spawns at once, later spawns queue up and
+    // reuse those threads; None lifts the limit for subsequent spawns
+    pub fn set_max_concurrency(&self, limit: Option<usize>) {
+        assert!(limit != Some(0), "concurrency limit must be positive");
+        self.workers.lock().unwrap().limit = limit;
+    }
+
+    // panics of closures passed to spawn go to handler instead of being re-raised when the
+    // scope joins them; spawn_async keeps reporting panics through its future
+    pub fn set_panic_handler<Func>(&self, handler: Func)
+        where Func: 'scope + Fn(&PanickedTask, PanicPayload) + Send + Sync
+    {
+        self.workers.lock().unwrap().panic_handler = Some(Arc::new(handler));
+    }
+
+    // applies to everything still pending when the callbacks run
+    pub fn set_defer_order(&self, order: DeferOrder) {
+        self.order.set(order);
+    }
+
+    // runs and joins everything deferred so far instead of waiting for the scope to end,
+    // re-raising the first panic once all of it ran
+    pub fn run_deferred_now(&self) {
+        if let Some(payload) = self.run_deferred().into_iter().next() {
+            panic::resume_unwind(payload);
+        }
+    }
+
+    #[track_caller]
+    pub fn spawn<Func>(&'scope self, f: Func)
+        where Func: 'scope + Send + FnOnce()
+    {
+        self.spawn_at(Location::caller(), Priority::Normal, f)
+    }
+
+    #[track_caller]
+    pub fn spawn_with_priority<Func>(&'scope self, priority: Priority, f: Func)
+        where Func: 'scope + Send + FnOnce()
+    {
+        self.spawn_at(Location::caller(), priority, f)
+    }
+
+    fn spawn_at<Func>(&self, site: &'static Location<'static>, priority: Priority, f: Func)
+        where Func: 'scope + Send + FnOnce()
+    {
+        let tracked = tasks::spawned(site);
+        let task = instrument::spawned();
+        let run = Task {
+            run: Box::new(move || {
+                tracked.started();
+                let _running = instrument::started(task);
+                f();
+            }),
+            spawned_at: site,
+            priority
+        };
+        {
+            let mut workers = self.workers.lock().unwrap();
+            if workers.limit.is_some_and(|limit| workers.running >= limit) {
+                let at = workers.tasks.iter().position(|queued| queued.priority < priority)
+                    .unwrap_or(workers.tasks.len());
+                workers.tasks.insert(at, run);
+                return;
+            }
+            workers.running += 1;
+        }
+        self.start_worker(run);
+    }
+
+    // runs first and then queued tasks until the queue is empty; a panicking task doesn't
+    // stop the worker, without a panic handler the first payload is re-raised when it is joined
+    fn start_worker(&self, first: Task<'scope>) {
+        let workers = &*self.workers;
+        let config = self.config.clone().unwrap_or_else(threads::default_config);
+        // every thread is joined by run_deferred before the scope ends, even if
+        // the closure passed to enter or another deferred callback panics
+        let to_join = unsafe {
+            config.spawn_unchecked(move || {
+                WORKER.with(|worker| worker.set(Some(Worker {
+                    workers: workers as *const Mutex<Workers> as *const (),
+                    run_queued
+                })));
+                let mut next = Some(first);
+                while let Some(task) = next {
+                    run_task(workers, task);
+                    let mut workers = workers.lock().unwrap();
+                    next = workers.tasks.pop_front();
+                    if next.is_none() {
+                        workers.running -= 1;
+                    }
+                }
+                WORKER.with(|worker| worker.set(None));
+                if let Some(payload) = WORKER_PANIC.with(|first| first.borrow_mut().take()) {
+                    panic::resume_unwind(payload);
+                }
+            })
+        }.expect("failed to spawn thread");
+        self.to_run.lock().unwrap().push(Box::new(move || {
+            if let Err(payload) = to_join.join() {
+                panic::resume_unwind(payload);
+            }
+        }));
+    }
+
+    #[track_caller]
+    pub fn spawn_async<Func, R>(&'scope self, f: Func) -> Future<'env, R>
+        where Func: 'scope + Send + FnOnce() -> R,
+              R: 'scope + Send
+    {
+        self.spawn_async_with_priority(Priority::Normal, f)
+    }
+
+    #[track_caller]
+    pub fn spawn_async_with_priority<Func, R>(&'scope self, priority: Priority, f: Func) -> Future<'env, R>
+        where Func: 'scope + Send + FnOnce() -> R,
+              R: 'scope + Send
+    {
+        let (promise, future) = Promise::new();
+        self.spawn_with_priority(priority, move || {
+            promise.set_result(panic::catch_unwind(AssertUnwindSafe(f)));
+        });
+        future
+    }
+
+    // parallelism workers pull items and run f on them until the iterator is exhausted;
+    // the future fails with the first panic of f
+    pub fn async_for_each<I, Func>(&'scope self, items: I, parallelism: usize, f: Func) -> Future<'env, ()>
+        where I: IntoIterator,
+              I::IntoIter: 'scope + Send,
+              Func: 'scope + Fn(I::Item) + Send + Sync
+    {
+        assert!(parallelism > 0, "parallelism must be positive");
+        let items = Arc::new(Mutex::new(items.into_iter()));
+        let f = Arc::new(f);
+        let workers: Vec<_> = (0..parallelism).map(|_| {
+            let (items, f) = (items.clone(), f.clone());
+            self.spawn_async(move || loop {
+                let item = items.lock().unwrap().next();
+                match item {
+                    Some(item) => f(item),
+                    None => break
+                }
+            })
+        }).collect();
+        traverse(workers, |worker| worker).apply(|_| ())
+    }
+
+    // runs everything deferred so far, including callbacks deferred while running,
+    // and reports panics only after all of them ran
+    fn run_deferred(&self) -> Vec<PanicPayload> {
+        let mut panics = Vec::new();
+        loop {
+            let mut callbacks = mem::take(&mut *self.to_run.lock().unwrap());
+            if callbacks.is_empty() {
+                break;
+            }
+            if self.order.get() == DeferOrder::Lifo {
+                callbacks.reverse();
+            }
+            callbacks.into_iter().for_each(|callback| {
+                if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(callback)) {
+                    panics.push(payload);
+                }
+            });
+        }
+        panics
+    }
+
+    // ManuallyDrop keeps the worker state out of drop glue, so whatever it owns is
+    // released here once every worker has been joined
+    fn release(&self) {
+        let mut workers = self.workers.lock().unwrap();
+        drop(mem::take(&mut workers.tasks));
+        drop(workers.panic_handler.take());
+    }
+}
+
+#[derive(Debug)]
+pub struct ScopeError {
+    // the payload if the closure passed to try_enter panicked itself
+    pub body: Option<PanicPayload>,
+    // from spawned threads and deferred callbacks, in the order they were joined
+    pub spawned: Vec<PanicPayload>
+}
+
+fn run_scope<'env, Func, R>(config: Option<ThreadConfig>, order: DeferOrder, cancellation: Cancellation, f: Func)
+    -> Result<R, ScopeError>
+    where Func: for<'scope> FnOnce(&'scope DeferScope<'scope, 'env>) -> R
+{
+    let scope = DeferScope::new(config, order, cancellation);
+    let result = {
+        let _current = Current::enter(&scope);
+        panic::catch_unwind(AssertUnwindSafe(|| f(&scope)))
+    };
+    let spawned = scope.run_deferred();
+    scope.release();
+    match result {
+        Ok(result) if spawned.is_empty() => Ok(result),
+        Ok(_) => Err(ScopeError{body: None, spawned}),
+        Err(payload) => Err(ScopeError{body: Some(payload), spawned})
+    }
+}
+
+fn resume_first<R>(result: Result<R, ScopeError>) -> R {
+    result.unwrap_or_else(|err| {
+        panic::resume_unwind(err.body.into_iter().chain(err.spawned).next().unwrap())
+    })
+}
+
+pub fn enter<'env, Func, R>(f: Func) -> R
+    where Func: for<'scope> FnOnce(&'scope DeferScope<'scope, 'env>) -> R
+{
+    resume_first(run_scope(None, DeferOrder::Fifo, Cancellation::new(None), f))
+}
+
+// like enter, but panics of the closure and of spawned threads come back as an error
+// once every thread has been joined
+pub fn try_enter<'env, Func, R>(f: Func) -> Result<R, ScopeError>
+    where Func: for<'scope> FnOnce(&'scope DeferScope<'scope, 'env>) -> R
+{
+    run_scope(None, DeferOrder::Fifo, Cancellation::new(None), f)
+}
+
+// threads spawned from this scope use config instead of the crate default
+pub fn enter_with<'env, Func, R>(config: ThreadConfig, f: Func) -> R
+    where Func: for<'scope> FnOnce(&'scope DeferScope<'scope, 'env>) -> R
+{
+    resume_first(run_scope(Some(config), DeferOrder::Fifo, Cancellation::new(None), f))
+}
+
+fn run_task<'scope>(workers: &Mutex<Workers<'scope>>, task: Task<'scope>) {
+    if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(task.run)) {
+        let handler = workers.lock().unwrap().panic_handler.clone();
+        match handler {
+            Some(handler) => {
+                let panicked = PanickedTask {
+                    spawned_at: task.spawned_at,
+                    thread_name: thread::current().name().map(String::from)
+                };
+                handler(&panicked, payload);
+            },
+            None => WORKER_PANIC.with(|first| {
+                first.borrow_mut().get_or_insert(payload);
+            })
+        }
+    }
+}
+
+// the worker whose thread this is, so tasks running on it can hand it to queued ones
+#[derive(Clone, Copy)]
+struct Worker {
+    workers: *const (),
+    run_queued: unsafe fn(*const ()) -> bool
+}
+
+thread_local! {
+    static WORKER: Cell<Option<Worker>> = const { Cell::new(None) };
+    // the first unhandled panic, re-raised when the worker is joined
+    static WORKER_PANIC: RefCell<Option<PanicPayload>> = const { RefCell::new(None) };
+}
+
+unsafe fn run_queued(workers: *const ()) -> bool {
+    let workers = &*(workers as *const Mutex<Workers<'_>>);
+    let task = workers.lock().unwrap().tasks.pop_front();
+    match task {
+        Some(task) => {
+            run_task(workers, task);
+            true
+        },
+        None => false
+    }
+}
+
+// called from a long running task on a scope worker, runs one task queued behind the
+// scope's concurrency limit before returning; returns whether there was one
+pub fn yield_now_if_contended() -> bool {
+    match WORKER.with(Cell::get) {
+        // the worker outlives everything running on its thread
+        Some(worker) => unsafe { (worker.run_queued)(worker.workers) },
+        None => false
+    }
+}
+
+// yields every n-th tick, for loops that don't want to check on each iteration
+pub struct Budget {
+    every: usize,
+    remaining: usize
+}
+
+impl Budget {
+    pub fn new(every: usize) -> Budget {
+        assert!(every > 0, "budget must be positive");
+        Budget{every, remaining: every}
+    }
+
+    // returns whether a queued task ran
+    pub fn tick(&mut self) -> bool {
+        self.remaining -= 1;
+        if self.remaining > 0 {
+            return false;
+        }
+        self.remaining = self.every;
+        yield_now_if_contended()
+    }
+}
+
+type SpawnErased = unsafe fn(*const (), &'static Location<'static>, Box<dyn FnOnce() + Send>);
+
+#[derive(Clone, Copy)]
+struct Current {
+    scope: *const (),
+    spawn: SpawnErased
+}
+
+thread_local! {
+    static CURRENT: RefCell<Vec<Current>> = const { RefCell::new(Vec::new()) };
+}
+
+unsafe fn spawn_erased(scope: *const (), site: &'static Location<'static>, f: Box<dyn FnOnce() + Send>) {
+    (*(scope as *const DeferScope<'_, '_>)).spawn_at(site, Priority::Normal, f)
+}
+
+// keeps a scope registered as current for as long as its body runs
+struct Registered;
+
+impl Current {
+    fn enter(scope: &DeferScope) -> Registered {
+        CURRENT.with(|current| current.borrow_mut().push(Current {
+            scope: scope as *const DeferScope as *const (),
+            spawn: spawn_erased
+        }));
+        Registered
+    }
+}
+
+impl Drop for Registered {
+    fn drop(&mut self) {
+        CURRENT.with(|current| current.borrow_mut().pop());
+    }
+}
+
+// the innermost scope whose body is running on this thread; work handed to it must be
+// 'static because the caller can't know what that scope borrows
+pub struct CurrentScope {
+    current: Current,
+    _not_send: PhantomData<*const ()>
+}
+
+impl CurrentScope {
+    #[track_caller]
+    pub fn spawn<Func>(&self, f: Func)
+        where Func: 'static + Send + FnOnce()
+    {
+        // the scope stays registered, and so alive, until its body returns, and the
+        // body can't return while the closure handed this reference is running
+        unsafe { (self.current.spawn)(self.current.scope, Location::caller(), Box::new(f)) }
+    }
+
+    #[track_caller]
+    pub fn spawn_async<Func, R>(&self, f: Func) -> Future<'static, R>
+        where Func: 'static + Send + FnOnce() -> R,
+              R: 'static + Send
+    {
+        let (promise, future) = Promise::new_static();
+        self.spawn(move || {
+            promise.set_result(panic::catch_unwind(AssertUnwindSafe(f)));
+        });
+        future
+    }
+}
+
+// calls f with the scope entered most recently on this thread, if any
+pub fn current_scope<Func, R>(f: Func) -> R
+    where Func: FnOnce(Option<&CurrentScope>) -> R
+{
+    let current = CURRENT.with(|current| current.borrow().last().copied());
+    let scope = current.map(|current| CurrentScope{current, _not_send: PhantomData});
+    f(scope.as_ref())
+}
+
+#[track_caller]
+pub fn spawn_async<Func, R>(f: Func) -> Future<'static, R>
+    where Func: 'static + Send + FnOnce() -> R,
+          R: 'static + Send
+{
+    spawn_async_on(&threads::default_config(), f)
+}
+
+#[track_caller]
+pub(crate) fn spawn_async_on<Func, R>(config: &ThreadConfig, f: Func) -> Future<'static, R>
+    where Func: 'static + Send + FnOnce() -> R,
+          R: 'static + Send
+{
+    let (promise, future) = Promise::new_static();
+    let tracked = tasks::spawned(Location::caller());
+    let task = instrument::spawned();
+    config.spawn(move || {
+        tracked.started();
+        let _running = instrument::started(task);
+        promise.set_result(panic::catch_unwind(AssertUnwindSafe(f)));
+    }).expect("failed to spawn thread");
+    future
+}
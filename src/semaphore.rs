@@ -0,0 +1,244 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use event::{Parker, Unparker};
+use future::{Future, Promise};
+
+struct Permits {
+    available: usize,
+    // blocked acquirers in arrival order, only the front one may take permits, so it's
+    // the only one woken up
+    queue: VecDeque<(u64, Unparker)>,
+    next_ticket: u64
+}
+
+impl Permits {
+    fn wake_front(&self) {
+        if let Some((_, unparker)) = self.queue.front() {
+            unparker.unpark();
+        }
+    }
+}
+
+// permits are handed out first come first served, so a large acquire isn't starved by
+// a stream of small ones; it does hold back smaller acquires queued behind it
+pub struct Semaphore {
+    permits: Mutex<Permits>
+}
+
+impl Semaphore {
+    pub fn new(permits: usize) -> Semaphore {
+        Semaphore {
+            permits: Mutex::new(Permits {
+                available: permits,
+                queue: VecDeque::new(),
+                next_ticket: 0
+            })
+        }
+    }
+
+    pub fn acquire(&self, n: usize) {
+        self.acquire_until(n, None);
+    }
+
+    // gives up once timeout passes, leaving its place in the queue to the next one
+    pub fn try_acquire_for(&self, n: usize, timeout: Duration) -> bool {
+        self.acquire_until(n, Some(Instant::now() + timeout))
+    }
+
+    fn acquire_until(&self, n: usize, deadline: Option<Instant>) -> bool {
+        let mut permits = self.permits.lock().unwrap();
+        if permits.queue.is_empty() && permits.available >= n {
+            permits.available -= n;
+            return true;
+        }
+        let parker = Parker::new();
+        let ticket = permits.next_ticket;
+        permits.next_ticket += 1;
+        permits.queue.push_back((ticket, parker.unparker()));
+        loop {
+            if permits.queue.front().map(|&(front, _)| front) == Some(ticket) && permits.available >= n {
+                permits.queue.pop_front();
+                permits.available -= n;
+                // the next in line may fit into what is left
+                permits.wake_front();
+                return true;
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                permits.queue.retain(|&(queued, _)| queued != ticket);
+                permits.wake_front();
+                return false;
+            }
+            drop(permits);
+            // a wake between dropping the lock and parking leaves the token set
+            match deadline {
+                None => {parker.park();},
+                Some(deadline) => {parker.park_deadline(deadline);}
+            }
+            permits = self.permits.lock().unwrap();
+        }
+    }
+
+    // fails rather than jumping ahead of blocked acquirers
+    pub fn try_acquire(&self, n: usize) -> bool {
+        let mut permits = self.permits.lock().unwrap();
+        if permits.queue.is_empty() && permits.available >= n {
+            permits.available -= n;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn release(&self, n: usize) {
+        let mut permits = self.permits.lock().unwrap();
+        permits.available += n;
+        permits.wake_front();
+    }
+
+    pub fn available(&self) -> usize {
+        self.permits.lock().unwrap().available
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Access {
+    Read,
+    Write
+}
+
+struct RwPermits {
+    readers: usize,
+    writer: bool,
+    // served in order, so a waiting writer holds back readers arriving after it
+    queue: VecDeque<(Access, Promise<'static, ()>)>
+}
+
+impl RwPermits {
+    fn admits(&self, access: Access) -> bool {
+        match access {
+            Access::Read => !self.writer,
+            Access::Write => !self.writer && self.readers == 0
+        }
+    }
+
+    fn take(&mut self, access: Access) {
+        match access {
+            Access::Read => {self.readers += 1;},
+            Access::Write => {self.writer = true;}
+        }
+    }
+
+    // pops everyone at the front who fits now, their promises are set outside the lock
+    fn grant(&mut self) -> Vec<Promise<'static, ()>> {
+        let mut granted = Vec::new();
+        while let Some(&(access, _)) = self.queue.front() {
+            if !self.admits(access) {
+                break;
+            }
+            let (_, promise) = self.queue.pop_front().unwrap();
+            // whoever was waiting on it is gone, so nobody would release the permit
+            if promise.has_consumers() {
+                self.take(access);
+                granted.push(promise);
+            }
+        }
+        granted
+    }
+}
+
+// many readers or a single writer, for long critical sections where a spinning
+// SpinRWLock would waste cores; the futures complete once the permit is held,
+// and the holder gives it back with release_read or release_write
+#[derive(Clone)]
+pub struct RwSemaphore {
+    permits: Arc<Mutex<RwPermits>>
+}
+
+impl Default for RwSemaphore {
+    fn default() -> RwSemaphore {
+        RwSemaphore::new()
+    }
+}
+
+impl RwSemaphore {
+    pub fn new() -> RwSemaphore {
+        RwSemaphore {
+            permits: Arc::new(Mutex::new(RwPermits {
+                readers: 0,
+                writer: false,
+                queue: VecDeque::new()
+            }))
+        }
+    }
+
+    fn acquire_async(&self, access: Access) -> Future<'static, ()> {
+        let mut permits = self.permits.lock().unwrap();
+        if permits.queue.is_empty() && permits.admits(access) {
+            permits.take(access);
+            return Future::new(());
+        }
+        let (promise, future) = Promise::new();
+        permits.queue.push_back((access, promise));
+        future
+    }
+
+    fn try_acquire(&self, access: Access) -> bool {
+        let mut permits = self.permits.lock().unwrap();
+        let admitted = permits.queue.is_empty() && permits.admits(access);
+        if admitted {
+            permits.take(access);
+        }
+        admitted
+    }
+
+    fn release(&self, access: Access) {
+        let granted = {
+            let mut permits = self.permits.lock().unwrap();
+            match access {
+                Access::Read => {
+                    assert!(permits.readers > 0, "no read permit to release");
+                    permits.readers -= 1;
+                },
+                Access::Write => {
+                    assert!(permits.writer, "no write permit to release");
+                    permits.writer = false;
+                }
+            }
+            permits.grant()
+        };
+        granted.into_iter().for_each(|promise| promise.set(()));
+    }
+
+    pub fn read_async(&self) -> Future<'static, ()> {
+        self.acquire_async(Access::Read)
+    }
+
+    pub fn write_async(&self) -> Future<'static, ()> {
+        self.acquire_async(Access::Write)
+    }
+
+    pub fn acquire_read(&self) {
+        self.read_async().take()
+    }
+
+    pub fn acquire_write(&self) {
+        self.write_async().take()
+    }
+
+    pub fn try_acquire_read(&self) -> bool {
+        self.try_acquire(Access::Read)
+    }
+
+    pub fn try_acquire_write(&self) -> bool {
+        self.try_acquire(Access::Write)
+    }
+
+    pub fn release_read(&self) {
+        self.release(Access::Read)
+    }
+
+    pub fn release_write(&self) {
+        self.release(Access::Write)
+    }
+}
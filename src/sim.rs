@@ -0,0 +1,109 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::time::Duration;
+use std::panic::{self, AssertUnwindSafe};
+use future::{Future, Promise, Either, select};
+
+struct SimState<'t> {
+    now: Duration,
+    rng: u64,
+    sequence: u64,
+    tasks: Vec<Box<dyn 't + FnOnce()>>,
+    timers: BTreeMap<(Duration, u64), Promise<'t, ()>>
+}
+
+impl<'t> SimState<'t> {
+    // xorshift64*, good enough to pick the next task reproducibly
+    fn next_random(&mut self) -> u64 {
+        self.rng ^= self.rng >> 12;
+        self.rng ^= self.rng << 25;
+        self.rng ^= self.rng >> 27;
+        self.rng.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+}
+
+// single-threaded executor: tasks run one at a time in an order derived from the seed,
+// and the clock only moves forward when nothing is runnable. Only work going through it
+// is simulated: spawn_async stands in for scope spawns, after and timeout for timers.
+// DeferScope still spawns real threads, and the blocking waits of futures and events
+// still use the real clock, so simulated code must not use them
+pub struct Simulation<'t> {
+    state: RefCell<SimState<'t>>
+}
+
+impl<'t> Simulation<'t> {
+    pub fn new(seed: u64) -> Simulation<'t> {
+        Simulation {
+            state: RefCell::new(SimState {
+                now: Duration::from_secs(0),
+                rng: seed | 1,
+                sequence: 0,
+                tasks: Vec::new(),
+                timers: BTreeMap::new()
+            })
+        }
+    }
+
+    pub fn now(&self) -> Duration {
+        self.state.borrow().now
+    }
+
+    pub fn spawn_async<Func, R>(&self, f: Func) -> Future<'t, R>
+        where Func: 't + FnOnce() -> R,
+              R: 't
+    {
+        let (promise, future) = Promise::new();
+        self.state.borrow_mut().tasks.push(Box::new(move || {
+            promise.set_result(panic::catch_unwind(AssertUnwindSafe(f)));
+        }));
+        future
+    }
+
+    pub fn after(&self, delay: Duration) -> Future<'t, ()> {
+        let (promise, future) = Promise::new();
+        let mut state = self.state.borrow_mut();
+        let key = (state.now + delay, state.sequence);
+        state.sequence += 1;
+        state.timers.insert(key, promise);
+        future
+    }
+
+    // None once delay passes in virtual time before future is done; the timer stays
+    // queued either way, so the clock may still move on to it
+    pub fn timeout<T>(&self, future: Future<'t, T>, delay: Duration) -> Future<'t, Option<T>>
+        where T: 't + Send
+    {
+        select(future, self.after(delay)).apply(|winner| match winner {
+            Either::Left(value) => Some(value),
+            Either::Right(()) => None
+        })
+    }
+
+    // returns false once there are no tasks and no timers left
+    pub fn step(&self) -> bool {
+        let task = {
+            let mut state = self.state.borrow_mut();
+            if state.tasks.is_empty() {
+                let next = state.timers.keys().next().cloned();
+                match next {
+                    None => {return false;},
+                    Some(key) => {
+                        let promise = state.timers.remove(&key).unwrap();
+                        state.now = key.0;
+                        drop(state);
+                        promise.set(());
+                        return true;
+                    }
+                }
+            }
+            let idx = (state.next_random() % state.tasks.len() as u64) as usize;
+            state.tasks.swap_remove(idx)
+        };
+        task();
+        true
+    }
+
+    pub fn run(&self) {
+        while self.step() {}
+    }
+}
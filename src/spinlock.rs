@@ -1,14 +1,226 @@
-use std::sync::atomic::{Ordering, AtomicBool, AtomicI16};
-use std::ops::{DerefMut, Deref};
-use std::cell::UnsafeCell;
-use std::marker::PhantomData;
-use std::mem;
+use sync::{self, Ordering, AtomicBool, AtomicUsize};
+use backoff::Backoff;
+#[cfg(feature = "std")]
+use sync::{Mutex, Condvar};
+use core::ops::{DerefMut, Deref};
+use core::cell::UnsafeCell;
+use core::marker::PhantomData;
+use core::mem;
+use core::fmt;
+#[cfg(feature = "std")]
+use instrument;
+#[cfg(feature = "std")]
+use std::time::{Duration, Instant};
+#[cfg(feature = "alloc")]
+use alloc::sync::Arc;
+#[cfg(feature = "deadlock-detection")]
+use lockdebug::Ownership;
+
+// how a lock waits while it's held by someone else
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum SpinStrategy {
+    // only spin hints, for short critical sections or when there is nothing to yield to
+    Spin,
+    // escalates as in Backoff::snooze
+    #[default]
+    SpinThenYield,
+    // waits like SpinThenYield but gives up after that many failed attempts; only the
+    // try_ methods give up, the blocking ones wait past the bound
+    Bounded(usize)
+}
+
+// a lock with a Bounded strategy ran out of attempts, or a timed acquisition ran out of time
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SpinExhausted;
+
+// why Spinlock::try_lock or try_lock_for came back without a guard
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpinlockError {
+    // the value was handed out through share(), read it that way instead
+    Shared,
+    // the strategy's attempts or the timeout ran out, retrying may succeed
+    WouldBlock
+}
+
+impl From<SpinExhausted> for SpinlockError {
+    fn from(_: SpinExhausted) -> SpinlockError {
+        SpinlockError::WouldBlock
+    }
+}
+
+// a write guard was dropped while its thread was panicking, so the data may be half
+// updated; the guard still comes along for whoever can check or repair it
+pub struct PoisonError<G> {
+    guard: G
+}
+
+impl<G> PoisonError<G> {
+    pub fn into_inner(self) -> G {
+        self.guard
+    }
+
+    pub fn get_ref(&self) -> &G {
+        &self.guard
+    }
+
+    pub fn get_mut(&mut self) -> &mut G {
+        &mut self.guard
+    }
+
+    #[cfg(feature = "alloc")]
+    fn map<U, Func>(self, f: Func) -> PoisonError<U>
+        where Func: FnOnce(G) -> U
+    {
+        PoisonError{guard: f(self.guard)}
+    }
+}
+
+impl<G> fmt::Debug for PoisonError<G> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PoisonError").finish_non_exhaustive()
+    }
+}
+
+pub type LockResult<G> = Result<G, PoisonError<G>>;
+
+pub enum TryLockError<G> {
+    Poisoned(PoisonError<G>),
+    Exhausted
+}
+
+impl<G> From<SpinExhausted> for TryLockError<G> {
+    fn from(_: SpinExhausted) -> TryLockError<G> {
+        TryLockError::Exhausted
+    }
+}
+
+impl<G> From<PoisonError<G>> for TryLockError<G> {
+    fn from(error: PoisonError<G>) -> TryLockError<G> {
+        TryLockError::Poisoned(error)
+    }
+}
+
+impl<G> fmt::Debug for TryLockError<G> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TryLockError::Poisoned(error) => f.debug_tuple("Poisoned").field(error).finish(),
+            TryLockError::Exhausted => f.write_str("Exhausted")
+        }
+    }
+}
+
+pub type TryLockResult<G> = Result<G, TryLockError<G>>;
+
+#[cfg(feature = "alloc")]
+fn map_lock<G, U, Func>(result: LockResult<G>, f: Func) -> LockResult<U>
+    where Func: FnOnce(G) -> U
+{
+    match result {
+        Ok(guard) => Ok(f(guard)),
+        Err(error) => Err(error.map(f))
+    }
+}
+
+#[cfg(feature = "alloc")]
+fn map_try_lock<G, U, Func>(result: TryLockResult<G>, f: Func) -> TryLockResult<U>
+    where Func: FnOnce(G) -> U
+{
+    match result {
+        Ok(guard) => Ok(f(guard)),
+        Err(TryLockError::Poisoned(error)) => Err(TryLockError::Poisoned(error.map(f))),
+        Err(TryLockError::Exhausted) => Err(TryLockError::Exhausted)
+    }
+}
+
+struct Spinner {
+    strategy: SpinStrategy,
+    backoff: Backoff,
+    attempts: usize,
+    #[cfg(feature = "std")]
+    deadline: Option<Instant>
+}
+
+impl Spinner {
+    fn new(strategy: SpinStrategy) -> Spinner {
+        Spinner {
+            strategy,
+            backoff: Backoff::new(),
+            attempts: 0,
+            #[cfg(feature = "std")]
+            deadline: None
+        }
+    }
+
+    // for the blocking methods, which have no way to report running out of attempts
+    fn blocking(strategy: SpinStrategy) -> Spinner {
+        match strategy {
+            SpinStrategy::Bounded(_) => Spinner::new(SpinStrategy::SpinThenYield),
+            strategy => Spinner::new(strategy)
+        }
+    }
+
+    // gives up at the deadline on top of whatever the strategy does
+    #[cfg(feature = "std")]
+    fn until(strategy: SpinStrategy, deadline: Instant) -> Spinner {
+        Spinner{deadline: Some(deadline), ..Spinner::new(strategy)}
+    }
+
+    fn wait(&mut self) -> Result<(), SpinExhausted> {
+        #[cfg(feature = "std")]
+        if self.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return Err(SpinExhausted);
+        }
+        match self.strategy {
+            SpinStrategy::Spin => sync::spin_loop(),
+            SpinStrategy::SpinThenYield => self.backoff.snooze(),
+            SpinStrategy::Bounded(limit) => {
+                if self.attempts >= limit {
+                    return Err(SpinExhausted);
+                }
+                self.attempts += 1;
+                self.backoff.snooze();
+            }
+        }
+        Ok(())
+    }
+}
+
+// stands in for a value Debug couldn't get at without waiting
+pub(crate) struct Locked;
+
+impl fmt::Debug for Locked {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("<locked>")
+    }
+}
+
+// runs its closure when dropped, unwinding included, so a panicking peek still
+// releases the lock
+pub(crate) struct Release<F: FnMut()>(pub(crate) F);
+
+impl<F: FnMut()> Drop for Release<F> {
+    fn drop(&mut self) {
+        (self.0)()
+    }
+}
+
+// once per acquisition, however long it spins
+pub(crate) fn report_contention(contended: &mut bool) {
+    if !*contended {
+        *contended = true;
+        #[cfg(feature = "std")]
+        instrument::emit(|instrument| instrument.lock_contended());
+    }
+}
 
 #[derive(Default)]
 pub struct Spinlock<T> {
     locked: AtomicBool,
     data: UnsafeCell<T>,
-    read_only: AtomicBool
+    read_only: AtomicBool,
+    strategy: SpinStrategy,
+    #[cfg(feature = "deadlock-detection")]
+    owner: Ownership
 }
 
 unsafe impl<T: Send> Sync for Spinlock<T> {} //we don't allow to share() !Sync values
@@ -21,7 +233,9 @@ pub struct SpinlockGuard<'t, T: 't> {
 
 impl<'t, T: 't> Drop for SpinlockGuard<'t, T> {
     fn drop(self: &mut SpinlockGuard<'t, T>) {
-        self.parent.locked.store(false, Ordering::Release);
+        #[cfg(feature = "deadlock-detection")]
+        self.parent.owner.write_unlocked();
+        self.parent.unlock();
     }
 }
 
@@ -41,49 +255,195 @@ impl<'t, T: 't> DerefMut for SpinlockGuard<'t, T> {
 
 impl<T> Spinlock<T> {
     pub fn new(value: T) -> Spinlock<T> {
+        Spinlock::with_strategy(value, SpinStrategy::default())
+    }
+
+    pub fn with_strategy(value: T, strategy: SpinStrategy) -> Spinlock<T> {
         Spinlock {
             locked: AtomicBool::new(false),
             read_only: AtomicBool::new(false),
-            data: UnsafeCell::from(value)
+            data: UnsafeCell::from(value),
+            strategy,
+            #[cfg(feature = "deadlock-detection")]
+            owner: Ownership::default()
         }
     }
 
+    fn unlock(self: &Spinlock<T>) {
+        self.locked.store(false, Ordering::Release);
+    }
+
     fn read_only(self: &Spinlock<T>) -> bool {
         self.read_only.load(Ordering::Acquire)
     }
 
-    fn take(self: &Spinlock<T>) -> bool {
-        while !self.locked.compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed).is_ok() {
+    fn take(self: &Spinlock<T>, mut spinner: Spinner) -> Result<(), SpinlockError> {
+        #[cfg(feature = "deadlock-detection")]
+        self.owner.before_write();
+        let mut contended = false;
+        while self.locked.compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed).is_err() {
             if self.read_only() {
-                return false;
+                return Err(SpinlockError::Shared);
             }
+            report_contention(&mut contended);
+            spinner.wait()?;
         }
-        true
+        Ok(())
     }
 
+    // None once the value is shared, waits past a Bounded strategy's attempts
     pub fn lock<'t>(self: &'t Spinlock<T>) -> Option<SpinlockGuard<'t, T>> {
-        if self.take() {
-            Some(SpinlockGuard{parent: self, _marker: PhantomData})
-        } else {
-            None
+        self.lock_with(Spinner::blocking(self.strategy)).ok()
+    }
+
+    // Shared once the value is shared, WouldBlock when a Bounded strategy runs out of
+    // attempts
+    pub fn try_lock<'t>(self: &'t Spinlock<T>) -> Result<SpinlockGuard<'t, T>, SpinlockError> {
+        self.lock_with(Spinner::new(self.strategy))
+    }
+
+    // WouldBlock as well if the lock is still held after timeout
+    #[cfg(feature = "std")]
+    pub fn try_lock_for<'t>(self: &'t Spinlock<T>, timeout: Duration) -> Result<SpinlockGuard<'t, T>, SpinlockError> {
+        self.lock_with(Spinner::until(self.strategy, Instant::now() + timeout))
+    }
+
+    fn lock_with<'t>(self: &'t Spinlock<T>, spinner: Spinner) -> Result<SpinlockGuard<'t, T>, SpinlockError> {
+        self.take(spinner)?;
+        #[cfg(feature = "deadlock-detection")]
+        self.owner.write_locked();
+        Ok(SpinlockGuard{parent: self, _marker: PhantomData})
+    }
+}
+
+impl<T> Spinlock<T> {
+    // one attempt, bypassing deadlock detection so Debug works for the holder too
+    pub(crate) fn try_peek<R, Func>(&self, f: Func) -> Option<R>
+        where Func: FnOnce(&T) -> R
+    {
+        if !self.read_only() {
+            self.locked.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed).ok()?;
         }
+        let _release = Release(|| if !self.read_only() {self.unlock()});
+        Some(f(unsafe {&*self.data.get()}))
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Spinlock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut out = f.debug_struct("Spinlock");
+        match self.try_peek(|data| {out.field("data", data);}) {
+            Some(()) => {},
+            None => {out.field("data", &Locked);}
+        }
+        out.field("shared", &self.read_only()).finish()
     }
 }
 
 impl<T: Sync> Spinlock<T> {
     pub fn share(self: &Spinlock<T>) -> &T {
-        if !self.read_only() {
-            self.take();
+        // sharing can't fail, so it waits past any bound
+        if !self.read_only() && self.take(Spinner::new(SpinStrategy::SpinThenYield)).is_ok() {
             self.read_only.store(true, Ordering::Release)
         }
         unsafe {mem::transmute(self.data.get())}
     }
 }
 
+// waits for changes to state behind a Spinlock; notifications bump a generation, so
+// one that lands between releasing the lock and parking isn't lost. Without std
+// waiters spin instead of parking
+pub struct SpinCondvar {
+    generation: AtomicUsize,
+    #[cfg(feature = "std")]
+    sleep: Mutex<()>,
+    #[cfg(feature = "std")]
+    wake: Condvar
+}
+
+impl Default for SpinCondvar {
+    fn default() -> SpinCondvar {
+        SpinCondvar::new()
+    }
+}
+
+impl SpinCondvar {
+    pub fn new() -> SpinCondvar {
+        SpinCondvar {
+            generation: AtomicUsize::new(0),
+            #[cfg(feature = "std")]
+            sleep: Mutex::new(()),
+            #[cfg(feature = "std")]
+            wake: Condvar::new()
+        }
+    }
+
+    // may wake up spuriously, like any condvar
+    pub fn wait<'t, T>(&self, guard: SpinlockGuard<'t, T>) -> SpinlockGuard<'t, T> {
+        let seen = self.generation.load(Ordering::Acquire);
+        let parent = guard.parent;
+        drop(guard);
+        self.park(seen);
+        parent.lock().expect("spinlock shared while waiting on it")
+    }
+
+    pub fn wait_while<'t, T, Func>(&self, mut guard: SpinlockGuard<'t, T>, mut cond: Func) -> SpinlockGuard<'t, T>
+        where Func: FnMut(&mut T) -> bool
+    {
+        while cond(&mut guard) {
+            guard = self.wait(guard);
+        }
+        guard
+    }
+
+    #[cfg(feature = "std")]
+    fn park(&self, seen: usize) {
+        let mut sleep = self.sleep.lock().unwrap();
+        while self.generation.load(Ordering::Acquire) == seen {
+            sleep = self.wake.wait(sleep).unwrap();
+        }
+    }
+
+    #[cfg(not(feature = "std"))]
+    fn park(&self, seen: usize) {
+        let mut backoff = Backoff::new();
+        while self.generation.load(Ordering::Acquire) == seen {
+            backoff.snooze();
+        }
+    }
+
+    pub fn notify_one(&self) {
+        self.generation.fetch_add(1, Ordering::Release);
+        #[cfg(feature = "std")]
+        {
+            drop(self.sleep.lock().unwrap());
+            self.wake.notify_one();
+        }
+    }
+
+    pub fn notify_all(&self) {
+        self.generation.fetch_add(1, Ordering::Release);
+        #[cfg(feature = "std")]
+        {
+            drop(self.sleep.lock().unwrap());
+            self.wake.notify_all();
+        }
+    }
+}
+
 pub struct SpinRWLock<T> {
     data: UnsafeCell<T>,
-    readers: AtomicI16,
-    write: AtomicBool
+    // every guard lives somewhere in memory, so a usize count can't overflow
+    readers: AtomicUsize,
+    write: AtomicBool,
+    // held by the writer or the upgradable reader, so an upgrade never competes with a
+    // writer that is itself waiting for the upgrading reader to leave
+    upgrader: AtomicBool,
+    // only set with std, there is no telling whether a thread unwinds without it
+    poisoned: AtomicBool,
+    strategy: SpinStrategy,
+    #[cfg(feature = "deadlock-detection")]
+    owner: Ownership
 }
 
 unsafe impl<T: Send + Sync> Sync for SpinRWLock<T> {}
@@ -121,46 +481,464 @@ impl<'t, T: 't> DerefMut for SpinWriteGuard<'t, T> {
     }
 }
 
+impl<'t, T: 't> SpinWriteGuard<'t, T> {
+    // the read is counted before the write flag drops, so no writer can get in between
+    pub fn downgrade(self) -> SpinReadGuard<'t, T> {
+        let parent = self.parent;
+        mem::forget(self);
+        parent.readers.fetch_add(1, Ordering::SeqCst);
+        #[cfg(feature = "deadlock-detection")]
+        {
+            parent.owner.write_unlocked();
+            parent.owner.read_locked();
+        }
+        parent.write.store(false, Ordering::Release);
+        parent.upgrader.store(false, Ordering::Release);
+        SpinReadGuard {
+            parent,
+            _marker: PhantomData
+        }
+    }
+
+    pub fn downgrade_to_upgradable(self) -> SpinUpgradableGuard<'t, T> {
+        let parent = self.parent;
+        mem::forget(self);
+        parent.readers.fetch_add(1, Ordering::SeqCst);
+        #[cfg(feature = "deadlock-detection")]
+        {
+            parent.owner.write_unlocked();
+            parent.owner.read_locked();
+        }
+        parent.write.store(false, Ordering::Release);
+        SpinUpgradableGuard {
+            parent,
+            _marker: PhantomData
+        }
+    }
+}
+
+// a read guard that can later become a write guard; plain readers may share the lock
+// with it, but there is at most one at a time and it excludes writers
+pub struct SpinUpgradableGuard<'t, T: 't> {
+    parent: &'t SpinRWLock<T>,
+    _marker: PhantomData<&'t T>
+}
+
+impl<'t, T: 't> Deref for SpinUpgradableGuard<'t, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe {&*self.parent.data.get()}
+    }
+}
+
+impl<'t, T: 't> SpinUpgradableGuard<'t, T> {
+    // new readers are held off from here on, so the wait for the current ones is finite
+    // and isn't bounded by the lock's strategy
+    pub fn upgrade(self) -> SpinWriteGuard<'t, T> {
+        let parent = self.parent;
+        mem::forget(self);
+        parent.write.store(true, Ordering::SeqCst);
+        parent.readers.fetch_sub(1, Ordering::SeqCst);
+        #[cfg(feature = "deadlock-detection")]
+        parent.owner.read_unlocked();
+        let mut contended = false;
+        let mut backoff = Backoff::new();
+        while parent.readers.load(Ordering::SeqCst) != 0 {
+            report_contention(&mut contended);
+            backoff.snooze();
+        }
+        #[cfg(feature = "deadlock-detection")]
+        parent.owner.write_locked();
+        SpinWriteGuard {
+            parent,
+            _marker: PhantomData
+        }
+    }
+
+    pub fn downgrade(self) -> SpinReadGuard<'t, T> {
+        let parent = self.parent;
+        mem::forget(self);
+        parent.upgrader.store(false, Ordering::Release);
+        SpinReadGuard {
+            parent,
+            _marker: PhantomData
+        }
+    }
+}
+
 impl<T> SpinRWLock<T> {
     pub fn new(val: T) -> Self {
+        SpinRWLock::with_strategy(val, SpinStrategy::default())
+    }
+
+    pub fn with_strategy(val: T, strategy: SpinStrategy) -> Self {
         SpinRWLock {
             data: UnsafeCell::new(val),
-            readers: AtomicI16::new(0),
-            write: AtomicBool::new(false)
+            readers: AtomicUsize::new(0),
+            write: AtomicBool::new(false),
+            upgrader: AtomicBool::new(false),
+            poisoned: AtomicBool::new(false),
+            strategy,
+            #[cfg(feature = "deadlock-detection")]
+            owner: Ownership::default()
         }
     }
 
-    pub fn read<'t>(&'t self) -> SpinReadGuard<'t, T> {
+    fn read_unlock(&self) {
+        let before = self.readers.fetch_sub(1, Ordering::Release);
+        debug_assert!(before > 0, "read guard released twice");
+    }
+
+    fn write_unlock(&self) {
+        #[cfg(feature = "std")]
+        if ::std::thread::panicking() {
+            self.poisoned.store(true, Ordering::Release);
+        }
+        self.write.store(false, Ordering::Release);
+        self.upgrader.store(false, Ordering::Release);
+    }
+
+    // one attempt at reading, bypassing deadlock detection like Spinlock::try_peek
+    pub(crate) fn try_peek<R, Func>(&self, f: Func) -> Option<R>
+        where Func: FnOnce(&T) -> R
+    {
+        self.readers.fetch_add(1, Ordering::SeqCst);
+        let _release = Release(|| self.read_unlock());
+        if self.write.load(Ordering::SeqCst) {
+            return None;
+        }
+        Some(f(unsafe {&*self.data.get()}))
+    }
+
+    // the borrow checker already rules out any guard, so no atomics are touched
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+
+    pub fn is_poisoned(&self) -> bool {
+        self.poisoned.load(Ordering::Acquire)
+    }
+
+    // for when the data was checked or repaired through the guard of a PoisonError
+    pub fn clear_poison(&self) {
+        self.poisoned.store(false, Ordering::Release);
+    }
+
+    fn check_poison<G>(&self, guard: G) -> LockResult<G> {
+        if self.is_poisoned() {
+            Err(PoisonError{guard})
+        } else {
+            Ok(guard)
+        }
+    }
+
+    fn try_check_poison<G>(&self, acquired: Result<G, SpinExhausted>) -> TryLockResult<G> {
+        Ok(self.check_poison(acquired?)?)
+    }
+
+    // includes readers that are momentarily backing off from a writer, so it's only a hint
+    pub fn reader_count(&self) -> usize {
+        self.readers.load(Ordering::Acquire)
+    }
+
+    // also true while a writer is still waiting for readers to leave
+    pub fn is_write_locked(&self) -> bool {
+        self.write.load(Ordering::Acquire)
+    }
+
+    // waits past a Bounded strategy's attempts, try_read gives up after them
+    pub fn read<'t>(&'t self) -> LockResult<SpinReadGuard<'t, T>> {
+        self.check_poison(self.read_with(Spinner::blocking(self.strategy)).expect("blocking spinner gave up"))
+    }
+
+    pub fn try_read<'t>(&'t self) -> TryLockResult<SpinReadGuard<'t, T>> {
+        self.try_check_poison(self.read_with(Spinner::new(self.strategy)))
+    }
+
+    #[cfg(feature = "std")]
+    pub fn try_read_for<'t>(&'t self, timeout: Duration) -> TryLockResult<SpinReadGuard<'t, T>> {
+        self.try_check_poison(self.read_with(Spinner::until(self.strategy, Instant::now() + timeout)))
+    }
+
+    fn read_with<'t>(&'t self, mut spinner: Spinner) -> Result<SpinReadGuard<'t, T>, SpinExhausted> {
+        #[cfg(feature = "deadlock-detection")]
+        self.owner.before_read();
+        let mut contended = false;
         loop {
-            self.readers.fetch_add(1, Ordering::SeqCst);
+            let before = self.readers.fetch_add(1, Ordering::SeqCst);
+            debug_assert!(before < usize::MAX, "read guard count overflow");
             if !self.write.load(Ordering::SeqCst) { break; }
             self.readers.fetch_sub(1, Ordering::SeqCst);
+            report_contention(&mut contended);
+            spinner.wait()?;
         }
-        SpinReadGuard {
+        #[cfg(feature = "deadlock-detection")]
+        self.owner.read_locked();
+        Ok(SpinReadGuard {
             parent: self,
             _marker: PhantomData
+        })
+    }
+
+    // waits past a Bounded strategy's attempts, try_write gives up after them
+    pub fn write<'t>(&'t self) -> LockResult<SpinWriteGuard<'t, T>> {
+        self.check_poison(self.write_with(Spinner::blocking(self.strategy)).expect("blocking spinner gave up"))
+    }
+
+    fn take_upgrader(&self, spinner: &mut Spinner, contended: &mut bool) -> Result<(), SpinExhausted> {
+        while self.upgrader.compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed).is_err() {
+            report_contention(contended);
+            spinner.wait()?;
         }
+        Ok(())
     }
 
-    pub fn write<'t>(&'t self) -> SpinWriteGuard<'t, T> {
-        while !self.write.compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed).is_ok() {}
-        while self.readers.load(Ordering::Acquire) != 0 {}
-        SpinWriteGuard {
+    // waits past a Bounded strategy's attempts, try_upgradable_read gives up after them
+    pub fn upgradable_read<'t>(&'t self) -> LockResult<SpinUpgradableGuard<'t, T>> {
+        self.check_poison(self.upgradable_read_with(Spinner::blocking(self.strategy)).expect("blocking spinner gave up"))
+    }
+
+    pub fn try_upgradable_read<'t>(&'t self) -> TryLockResult<SpinUpgradableGuard<'t, T>> {
+        self.try_check_poison(self.upgradable_read_with(Spinner::new(self.strategy)))
+    }
+
+    fn upgradable_read_with<'t>(&'t self, mut spinner: Spinner) -> Result<SpinUpgradableGuard<'t, T>, SpinExhausted> {
+        #[cfg(feature = "deadlock-detection")]
+        self.owner.before_write();
+        let mut contended = false;
+        self.take_upgrader(&mut spinner, &mut contended)?;
+        // holding the upgrader leaves no writer to wait for
+        self.readers.fetch_add(1, Ordering::SeqCst);
+        #[cfg(feature = "deadlock-detection")]
+        self.owner.read_locked();
+        Ok(SpinUpgradableGuard {
             parent: self,
             _marker: PhantomData
+        })
+    }
+
+    // the bound covers waiting for other writers and for readers to leave together
+    pub fn try_write<'t>(&'t self) -> TryLockResult<SpinWriteGuard<'t, T>> {
+        self.try_check_poison(self.write_with(Spinner::new(self.strategy)))
+    }
+
+    // the timeout covers waiting for readers to leave as well
+    #[cfg(feature = "std")]
+    pub fn try_write_for<'t>(&'t self, timeout: Duration) -> TryLockResult<SpinWriteGuard<'t, T>> {
+        self.try_check_poison(self.write_with(Spinner::until(self.strategy, Instant::now() + timeout)))
+    }
+
+    fn write_with<'t>(&'t self, mut spinner: Spinner) -> Result<SpinWriteGuard<'t, T>, SpinExhausted> {
+        #[cfg(feature = "deadlock-detection")]
+        self.owner.before_write();
+        let mut contended = false;
+        self.take_upgrader(&mut spinner, &mut contended)?;
+        self.write.store(true, Ordering::SeqCst);
+        while self.readers.load(Ordering::SeqCst) != 0 {
+            report_contention(&mut contended);
+            if let Err(exhausted) = spinner.wait() {
+                self.write.store(false, Ordering::Release);
+                self.upgrader.store(false, Ordering::Release);
+                return Err(exhausted);
+            }
+        }
+        #[cfg(feature = "deadlock-detection")]
+        self.owner.write_locked();
+        Ok(SpinWriteGuard {
+            parent: self,
+            _marker: PhantomData
+        })
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for SpinRWLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut out = f.debug_struct("SpinRWLock");
+        // the peek counts itself as a reader while it looks
+        let readers = self.reader_count();
+        match self.try_peek(|data| {out.field("data", data);}) {
+            Some(()) => {},
+            None => {out.field("data", &Locked);}
         }
+        out.field("readers", &readers)
+            .field("write_locked", &self.is_write_locked())
+            .field("poisoned", &self.is_poisoned())
+            .finish()
     }
 }
 
 impl<'t, T: 't> Drop for SpinWriteGuard<'t, T> {
     fn drop(&mut self) {
-        self.parent.write.store(false, Ordering::Release);
+        #[cfg(feature = "deadlock-detection")]
+        self.parent.owner.write_unlocked();
+        self.parent.write_unlock();
     }
 }
 
 impl<'t, T: 't> Drop for SpinReadGuard<'t, T> {
     fn drop(&mut self) {
+        #[cfg(feature = "deadlock-detection")]
+        self.parent.owner.read_unlocked();
+        self.parent.read_unlock();
+    }
+}
+
+impl<'t, T: 't> Drop for SpinUpgradableGuard<'t, T> {
+    fn drop(&mut self) {
+        #[cfg(feature = "deadlock-detection")]
+        self.parent.owner.read_unlocked();
         self.parent.readers.fetch_sub(1, Ordering::Release);
+        self.parent.upgrader.store(false, Ordering::Release);
+    }
+}
+
+
+// guards that keep their lock alive through an Arc instead of borrowing it, so they can
+// be moved into spawned tasks and continuations. They aren't tied to a thread, so
+// deadlock detection stops tracking them once they are created
+#[cfg(feature = "alloc")]
+pub struct OwnedSpinlockGuard<T> {
+    lock: Arc<Spinlock<T>>,
+    // the Arc alone would make the guard Sync for any T: Send
+    _marker: PhantomData<*mut T>
+}
+
+// as for a &mut T
+#[cfg(feature = "alloc")]
+unsafe impl<T: Send> Send for OwnedSpinlockGuard<T> {}
+#[cfg(feature = "alloc")]
+unsafe impl<T: Sync> Sync for OwnedSpinlockGuard<T> {}
+
+#[cfg(feature = "alloc")]
+impl<T> Spinlock<T> {
+    pub fn lock_owned(self: &Arc<Self>) -> Option<OwnedSpinlockGuard<T>> {
+        let guard = self.lock()?;
+        mem::forget(guard);
+        #[cfg(feature = "deadlock-detection")]
+        self.owner.write_unlocked();
+        Some(OwnedSpinlockGuard{lock: self.clone(), _marker: PhantomData})
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T> Deref for OwnedSpinlockGuard<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe {&*self.lock.data.get()}
     }
 }
 
+#[cfg(feature = "alloc")]
+impl<T> DerefMut for OwnedSpinlockGuard<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe {&mut *self.lock.data.get()}
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T> Drop for OwnedSpinlockGuard<T> {
+    fn drop(&mut self) {
+        self.lock.unlock();
+    }
+}
+
+#[cfg(feature = "alloc")]
+pub struct OwnedSpinReadGuard<T> {
+    lock: Arc<SpinRWLock<T>>,
+    _marker: PhantomData<*mut T>
+}
+
+// other readers see the same T at once, and the last Arc may drop it anywhere
+#[cfg(feature = "alloc")]
+unsafe impl<T: Send + Sync> Send for OwnedSpinReadGuard<T> {}
+#[cfg(feature = "alloc")]
+unsafe impl<T: Send + Sync> Sync for OwnedSpinReadGuard<T> {}
+
+#[cfg(feature = "alloc")]
+pub struct OwnedSpinWriteGuard<T> {
+    lock: Arc<SpinRWLock<T>>,
+    _marker: PhantomData<*mut T>
+}
+
+#[cfg(feature = "alloc")]
+unsafe impl<T: Send> Send for OwnedSpinWriteGuard<T> {}
+#[cfg(feature = "alloc")]
+unsafe impl<T: Sync> Sync for OwnedSpinWriteGuard<T> {}
+
+#[cfg(feature = "alloc")]
+impl<T> SpinRWLock<T> {
+    pub fn read_owned(self: &Arc<Self>) -> LockResult<OwnedSpinReadGuard<T>> {
+        map_lock(self.read(), |guard| self.own_read(guard))
+    }
+
+    pub fn try_read_owned(self: &Arc<Self>) -> TryLockResult<OwnedSpinReadGuard<T>> {
+        map_try_lock(self.try_read(), |guard| self.own_read(guard))
+    }
+
+    pub fn write_owned(self: &Arc<Self>) -> LockResult<OwnedSpinWriteGuard<T>> {
+        map_lock(self.write(), |guard| self.own_write(guard))
+    }
+
+    pub fn try_write_owned(self: &Arc<Self>) -> TryLockResult<OwnedSpinWriteGuard<T>> {
+        map_try_lock(self.try_write(), |guard| self.own_write(guard))
+    }
+
+    fn own_read(self: &Arc<Self>, guard: SpinReadGuard<T>) -> OwnedSpinReadGuard<T> {
+        mem::forget(guard);
+        #[cfg(feature = "deadlock-detection")]
+        self.owner.read_unlocked();
+        OwnedSpinReadGuard{lock: self.clone(), _marker: PhantomData}
+    }
+
+    fn own_write(self: &Arc<Self>, guard: SpinWriteGuard<T>) -> OwnedSpinWriteGuard<T> {
+        mem::forget(guard);
+        #[cfg(feature = "deadlock-detection")]
+        self.owner.write_unlocked();
+        OwnedSpinWriteGuard{lock: self.clone(), _marker: PhantomData}
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T> Deref for OwnedSpinReadGuard<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe {&*self.lock.data.get()}
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T> Drop for OwnedSpinReadGuard<T> {
+    fn drop(&mut self) {
+        self.lock.read_unlock();
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T> Deref for OwnedSpinWriteGuard<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe {&*self.lock.data.get()}
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T> DerefMut for OwnedSpinWriteGuard<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe {&mut *self.lock.data.get()}
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T> Drop for OwnedSpinWriteGuard<T> {
+    fn drop(&mut self) {
+        self.lock.write_unlock();
+    }
+}
@@ -0,0 +1,55 @@
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hash};
+use std::marker::PhantomData;
+use std::time::Duration;
+use spinlock::{Spinlock, SpinlockGuard};
+
+// per-key mutual exclusion over a fixed set of spinlocks, keys hashing to the same
+// stripe exclude each other too. Holding two keys' guards at once deadlocks when they
+// share a stripe, take them in stripe_of order and only once per stripe
+pub struct StripedLock<K: ?Sized, S = RandomState> {
+    stripes: Box<[Spinlock<()>]>,
+    hasher: S,
+    _key: PhantomData<fn(&K)>
+}
+
+impl<K: Hash + ?Sized> StripedLock<K> {
+    pub fn new(stripes: usize) -> StripedLock<K> {
+        StripedLock::with_hasher(stripes, RandomState::new())
+    }
+}
+
+impl<K: Hash + ?Sized, S: BuildHasher> StripedLock<K, S> {
+    pub fn with_hasher(stripes: usize, hasher: S) -> StripedLock<K, S> {
+        assert!(stripes > 0, "striped lock needs at least one stripe");
+        StripedLock {
+            stripes: (0..stripes).map(|_| Spinlock::new(())).collect(),
+            hasher,
+            _key: PhantomData
+        }
+    }
+
+    pub fn lock(&self, key: &K) -> SpinlockGuard<'_, ()> {
+        // the stripes are never shared and spin without bound
+        self.stripes[self.stripe_of(key)].lock().expect("stripe is never shared")
+    }
+
+    pub fn try_lock_for(&self, key: &K, timeout: Duration) -> Option<SpinlockGuard<'_, ()>> {
+        self.stripes[self.stripe_of(key)].try_lock_for(timeout).ok()
+    }
+
+    pub fn stripe_of(&self, key: &K) -> usize {
+        (self.hasher.hash_one(key) % self.stripes.len() as u64) as usize
+    }
+
+    pub fn stripe_count(&self) -> usize {
+        self.stripes.len()
+    }
+}
+
+impl<K: ?Sized, S> fmt::Debug for StripedLock<K, S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("StripedLock").field("stripes", &self.stripes.len()).finish()
+    }
+}
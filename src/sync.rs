@@ -0,0 +1,25 @@
+// every primitive the lock and future internals synchronize through, so the crate
+// can be built with RUSTFLAGS="--cfg loom" and model-checked
+
+#[cfg(all(feature = "std", not(loom)))]
+pub use std::sync::{Arc, Mutex, Condvar};
+#[cfg(all(feature = "alloc", not(feature = "std"), not(loom)))]
+pub use alloc::sync::Arc;
+#[cfg(not(loom))]
+pub use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+#[cfg(loom)]
+pub use loom::sync::{Arc, Mutex, Condvar};
+#[cfg(loom)]
+pub use loom::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+// loom can only explore spin loops that give other threads a chance to run
+#[cfg(not(loom))]
+pub fn spin_loop() {
+    ::core::hint::spin_loop()
+}
+
+#[cfg(loom)]
+pub fn spin_loop() {
+    ::loom::thread::yield_now()
+}
@@ -0,0 +1,100 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::panic::Location;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TaskState {
+    // spawned, but its thread hasn't picked it up yet
+    Queued,
+    Running
+}
+
+#[derive(Clone, Debug)]
+pub struct TaskInfo {
+    pub id: u64,
+    // the name of the thread running it, once it runs
+    pub name: Option<String>,
+    pub spawned_at: &'static Location<'static>,
+    pub state: TaskState,
+    pub age: Duration
+}
+
+struct Entry {
+    name: Option<String>,
+    spawned_at: &'static Location<'static>,
+    state: TaskState,
+    since: Instant
+}
+
+const SHARDS: usize = 16;
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+// sharded by id so concurrent spawns and exits mostly take different locks
+static REGISTRY: [Mutex<BTreeMap<u64, Entry>>; SHARDS] = [const { Mutex::new(BTreeMap::new()) }; SHARDS];
+
+fn registry(id: u64) -> ::std::sync::MutexGuard<'static, BTreeMap<u64, Entry>> {
+    REGISTRY[(id % SHARDS as u64) as usize].lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+// every task spawned by spawn_async() or a DeferScope that hasn't finished yet, oldest first.
+// shards are read one by one, so it isn't an atomic picture of all of them
+pub fn snapshot() -> Vec<TaskInfo> {
+    let now = Instant::now();
+    let mut tasks = Vec::new();
+    for shard in 0..SHARDS as u64 {
+        tasks.extend(registry(shard).iter()
+            .map(|(&id, entry)| TaskInfo {
+                id,
+                name: entry.name.clone(),
+                spawned_at: entry.spawned_at,
+                state: entry.state,
+                age: now.duration_since(entry.since)
+            }));
+    }
+    tasks.sort_unstable_by_key(|task| task.id);
+    tasks
+}
+
+pub fn dump() -> String {
+    let tasks = snapshot();
+    let mut report = format!("{} tasks in flight\n", tasks.len());
+    for task in tasks {
+        let _ = writeln!(report, "  #{} {:?} for {:?} on {} spawned at {}",
+                         task.id, task.state, task.age,
+                         task.name.as_deref().unwrap_or("<unnamed>"), task.spawned_at);
+    }
+    report
+}
+
+// unregisters the task when dropped, which the running thread does on exit or unwind
+pub(crate) struct Tracked(u64);
+
+pub(crate) fn spawned(spawned_at: &'static Location<'static>) -> Tracked {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    registry(id).insert(id, Entry {
+        name: None,
+        spawned_at,
+        state: TaskState::Queued,
+        since: Instant::now()
+    });
+    Tracked(id)
+}
+
+impl Tracked {
+    pub fn started(&self) {
+        if let Some(entry) = registry(self.0).get_mut(&self.0) {
+            entry.name = thread::current().name().map(String::from);
+            entry.state = TaskState::Running;
+        }
+    }
+}
+
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        registry(self.0).remove(&self.0);
+    }
+}
@@ -1,6 +1,8 @@
 use future::{Promise, Future, wait_all, wait_any};
-use async::{enter, async, DeferScope};
-use std::sync::Arc;
+use future::{try_join_all, traverse, traverse_limited, first_ok, BoxFuture, PanicPayload};
+use future::{Either, select, Ready};
+use scope::{enter, spawn_async};
+use std::sync::{Arc, Mutex};
 use std::sync::atomic::{AtomicI64, Ordering};
 use std::sync::mpsc::channel;
 use std::thread;
@@ -8,7 +10,15 @@ use std::time;
 use spinlock::Spinlock;
 use std::rc::Rc;
 use std::cell::RefCell;
+use std::panic;
 use atom::Atom;
+use memo::MemoCache;
+use actor::{self, Actor, Supervisor, Strategy};
+use pipeline;
+use ratelimit::RateLimiter;
+use breaker::{CircuitBreaker, BreakerError, BreakerState};
+use counter::ShardedCounter;
+use striped::StripedLock;
 
 #[test]
 fn check_spinlock() {
@@ -101,7 +111,7 @@ fn check_get() {
 
 #[test]
 fn check_static_async() {
-    let r = async(|| {
+    let r = spawn_async(|| {
         thread::sleep(time::Duration::from_millis(4));
         2 + 2
     });
@@ -113,9 +123,9 @@ fn check_asyncs() {
     let arr = [5, 4, 9];
     let mut x = arr[0];
     let sm = arr.iter().sum();
-    let res2 = async(move || sm);
+    let res2 = spawn_async(move || sm);
     let res1 = enter(|scope| {
-        let res1 = scope.async(|| {
+        let res1 = scope.spawn_async(|| {
             thread::sleep(time::Duration::from_millis(2));
             x += arr[1];
             x
@@ -136,14 +146,14 @@ fn check_wait_all() {
     let cnt = Arc::new(AtomicI64::new(0));
     let f1 = {
         let cnt = cnt.clone();
-        async(move || {
+        spawn_async(move || {
             thread::sleep(time::Duration::from_millis(40));
             cnt.fetch_add(1, Ordering::Relaxed);
         })
     };
     let f2 = {
         let cnt = cnt.clone();
-        async(move || {
+        spawn_async(move || {
             thread::sleep(time::Duration::from_millis(2));
             cnt.fetch_add(1, Ordering::Relaxed);
         })
@@ -155,7 +165,7 @@ fn check_wait_all() {
 fn check_hswap() {
     let x = Atom::<i64>::new(5);
     enter(|scope| {
-        scope.async(|| {
+        scope.spawn_async(|| {
             for i in 1..300 {
                 let z = x.load();
                 thread::sleep(time::Duration::from_millis(15));
@@ -165,7 +175,7 @@ fn check_hswap() {
                 }
             }
         });
-        scope.async(|| {
+        scope.spawn_async(|| {
             for i in 1..300 {
                 let z = x.load();
                 thread::sleep(time::Duration::from_millis(15));
@@ -175,10 +185,2339 @@ fn check_hswap() {
                 }
             }
         });
-        scope.async(|| {
+        scope.spawn_async(|| {
             for i in 1..400 {
                 x.store_val(i);
             }
         });
     })
 }
+
+#[test]
+fn check_memo_cache() {
+    let cache = MemoCache::new();
+    let computed = AtomicI64::new(0);
+    enter(|scope| {
+        for _ in 0..4 {
+            scope.spawn(|| {
+                let value = cache.get_or_compute(1, || {
+                    computed.fetch_add(1, Ordering::SeqCst);
+                    spawn_async(|| {
+                        thread::sleep(time::Duration::from_millis(10));
+                        42
+                    })
+                });
+                assert_eq!(*value.get(), 42);
+            });
+        }
+    });
+    assert_eq!(computed.load(Ordering::SeqCst), 1);
+    assert_eq!(*cache.get_or_compute(2, || Future::new(7)).get(), 7);
+    assert_eq!(cache.len(), 2);
+    assert!(cache.remove(&1).is_some());
+    assert!(cache.get(&1).is_none());
+}
+
+#[test]
+fn check_memo_cache_panicking_compute() {
+    let cache = MemoCache::new();
+    let waiting = RefCell::new(None);
+    let failed = panic::catch_unwind(panic::AssertUnwindSafe(|| cache.get_or_compute(1, || -> Future<i32> {
+        *waiting.borrow_mut() = cache.get(&1);
+        panic!("compute failed");
+    })));
+    assert!(failed.is_err());
+    let waiting = waiting.into_inner().unwrap();
+    assert!(panic::catch_unwind(panic::AssertUnwindSafe(|| *waiting.get())).is_err());
+    assert!(cache.get(&1).is_none());
+    assert_eq!(*cache.get_or_compute(1, || Future::new(2)).get(), 2);
+
+    // a future that only panics later is forgotten as well
+    let (promise, future) = Promise::new();
+    let pending = cache.get_or_compute(3, || future);
+    assert!(cache.get(&3).is_some());
+    promise.set_panic(Box::new("later"));
+    assert!(panic::catch_unwind(panic::AssertUnwindSafe(|| *pending.get())).is_err());
+    assert!(cache.get(&3).is_none());
+    assert_eq!(*cache.get_or_compute(3, || Future::new(4)).get(), 4);
+}
+
+struct Accumulator {
+    sum: i64
+}
+
+impl Actor for Accumulator {
+    type Message = i64;
+    type Reply = i64;
+
+    fn handle(&mut self, msg: i64) -> i64 {
+        self.sum += msg;
+        self.sum
+    }
+}
+
+#[test]
+fn check_actor() {
+    let addr = actor::spawn(Accumulator{sum: 0});
+    let other = addr.clone();
+    thread::spawn(move || {
+        for i in 1..5 {
+            other.send(i).unwrap();
+        }
+    }).join().unwrap();
+    assert_eq!(addr.ask(5).take(), 15);
+}
+
+struct Fragile {
+    handled: usize
+}
+
+impl Actor for Fragile {
+    type Message = bool;
+    type Reply = usize;
+
+    fn handle(&mut self, fail: bool) -> usize {
+        if fail {
+            panic!("requested failure");
+        }
+        self.handled += 1;
+        self.handled
+    }
+}
+
+#[test]
+fn check_actor_failed_asks() {
+    let message = |result: Result<usize, PanicPayload>| result.err().map(|payload| {
+        payload.downcast_ref::<String>().cloned().or_else(|| payload.downcast_ref::<&str>().map(|s| s.to_string())).unwrap()
+    });
+    let addr = actor::spawn(Fragile{handled: 0});
+    assert_eq!(addr.ask(false).take(), 1);
+    assert_eq!(addr.ask(true).apply_catch(message).take(), Some(String::from("requested failure")));
+    // the unsupervised actor is gone, later asks fail whether or not the mailbox closed yet
+    assert!(addr.ask(false).apply_catch(message).take().is_some());
+    while addr.send(false).is_ok() {
+        thread::yield_now();
+    }
+    assert_eq!(addr.ask(false).apply_catch(message).take(), Some(String::from("actor stopped before handling the message")));
+}
+
+#[test]
+fn check_supervisor() {
+    let supervisor = Supervisor::new(Strategy::AllForOne, 2, time::Duration::from_secs(60));
+    let first = supervisor.spawn(|| Fragile{handled: 0});
+    let second = supervisor.spawn(|| Fragile{handled: 0});
+    assert_eq!(first.ask(false).take(), 1);
+    assert_eq!(second.ask(false).take(), 1);
+    assert_eq!(second.ask(false).take(), 2);
+    first.send(true).unwrap();
+    assert_eq!(first.ask(false).take(), 1);
+    assert_eq!(second.ask(false).take(), 1);
+    first.send(true).unwrap();
+    assert_eq!(first.ask(false).take(), 1);
+    second.send(true).unwrap();
+    let escalation = supervisor.escalated();
+    assert_eq!(escalation.get().child, 1);
+    assert_eq!(escalation.get().restarts, 2);
+}
+
+#[test]
+fn check_supervised_failed_asks() {
+    let supervisor = Supervisor::new(Strategy::OneForOne, 1, time::Duration::from_secs(60));
+    let addr = supervisor.spawn(|| Fragile{handled: 0});
+    assert_eq!(addr.ask(false).take(), 1);
+    assert!(addr.ask(true).apply_catch(|result| result.is_err()).take());
+    assert_eq!(addr.ask(false).take(), 1);
+    // past max_restarts the child stops, the failing ask still resolves
+    assert!(addr.ask(true).apply_catch(|result| result.is_err()).take());
+    assert_eq!(supervisor.escalated().get().restarts, 1);
+    assert!(addr.ask(false).apply_catch(|result| result.is_err()).take());
+}
+
+struct Lifecycle {
+    log: Arc<Mutex<Vec<&'static str>>>
+}
+
+impl Actor for Lifecycle {
+    type Message = ();
+    type Reply = ();
+
+    fn handle(&mut self, _: ()) {
+        panic!("requested failure");
+    }
+
+    fn started(&mut self) {
+        self.log.lock().unwrap().push("started");
+    }
+
+    fn stopped(&mut self) {
+        self.log.lock().unwrap().push("stopped");
+    }
+}
+
+#[test]
+fn check_supervised_lifecycle() {
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let supervisor = Supervisor::new(Strategy::OneForOne, 1, time::Duration::from_secs(60));
+    let addr = {
+        let log = log.clone();
+        supervisor.spawn(move || Lifecycle{log: log.clone()})
+    };
+    addr.send(()).unwrap();
+    addr.send(()).unwrap();
+    // escalating comes after the failed instance is stopped
+    assert_eq!(supervisor.escalated().get().restarts, 1);
+    assert_eq!(*log.lock().unwrap(), vec!["started", "stopped", "started", "stopped"]);
+}
+
+#[test]
+fn check_pipeline() {
+    let total = Arc::new(AtomicI64::new(0));
+    let pipeline = {
+        let total = total.clone();
+        pipeline::Builder::new(2)
+            .stage(3, |x: i64| {
+                thread::sleep(time::Duration::from_millis(1));
+                x * 2
+            })
+            .stage(2, |x| x + 1)
+            .sink(1, move |x| {
+                total.fetch_add(x, Ordering::SeqCst);
+            })
+    };
+    for i in 0..100 {
+        pipeline.push(i).unwrap();
+    }
+    pipeline.finish().take();
+    assert_eq!(total.load(Ordering::SeqCst), 99 * 100 + 100);
+}
+
+#[test]
+fn check_rate_limiter() {
+    let limiter = RateLimiter::new(100.0, 3);
+    assert!(limiter.try_acquire());
+    assert!(limiter.try_acquire());
+    assert!(limiter.try_acquire());
+    assert!(!limiter.try_acquire());
+    let started = time::Instant::now();
+    limiter.acquire();
+    limiter.acquire_future().take();
+    assert!(started.elapsed() >= time::Duration::from_millis(15));
+}
+
+#[test]
+fn check_circuit_breaker() {
+    let breaker = CircuitBreaker::new(2, time::Duration::from_secs(60), time::Duration::from_millis(20));
+    let failing = || Future::new(Err::<i32, &str>("down"));
+    assert_eq!(breaker.call(failing).take(), Err(BreakerError::Inner("down")));
+    assert_eq!(breaker.call(failing).take(), Err(BreakerError::Inner("down")));
+    assert_eq!(breaker.state(), BreakerState::Open);
+    assert_eq!(breaker.call(|| -> Future<Result<i32, &str>> { panic!("called while open") }).take(),
+               Err(BreakerError::Open));
+    thread::sleep(time::Duration::from_millis(25));
+    let (promise, probe) = Promise::<Result<i32, &str>>::new();
+    let probe = breaker.call(move || probe);
+    assert_eq!(breaker.state(), BreakerState::HalfOpen);
+    assert_eq!(breaker.call(failing).take(), Err(BreakerError::Open));
+    promise.set(Ok(1));
+    assert_eq!(probe.take(), Ok(1));
+    assert_eq!(breaker.state(), BreakerState::Closed);
+}
+
+#[test]
+fn check_circuit_breaker_failed_probes() {
+    let cooldown = time::Duration::from_millis(5);
+    let breaker = CircuitBreaker::new(1, time::Duration::from_secs(60), cooldown);
+    assert!(breaker.call(|| Future::new(Err::<i32, &str>("down"))).take().is_err());
+    assert_eq!(breaker.state(), BreakerState::Open);
+
+    // a probe that panics while running
+    thread::sleep(cooldown);
+    let probe = breaker.call(|| spawn_async(|| -> Result<i32, &str> { panic!("probe failed") }));
+    assert!(panic::catch_unwind(panic::AssertUnwindSafe(|| probe.take())).is_err());
+    assert_eq!(breaker.state(), BreakerState::Open);
+
+    // one that panics before handing out its future
+    thread::sleep(cooldown);
+    let failed = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+        breaker.call(|| -> Future<Result<i32, &str>> { panic!("probe failed") })
+    }));
+    assert!(failed.is_err());
+    assert_eq!(breaker.state(), BreakerState::Open);
+
+    // and one whose promise is dropped
+    thread::sleep(cooldown);
+    let (promise, probe) = Promise::<Result<i32, &str>>::new();
+    let _probe = breaker.call(move || probe);
+    assert_eq!(breaker.state(), BreakerState::HalfOpen);
+    drop(promise);
+    assert_eq!(breaker.state(), BreakerState::Open);
+
+    thread::sleep(cooldown);
+    assert_eq!(breaker.call(|| Future::new(Ok::<i32, &str>(1))).take(), Ok(1));
+    assert_eq!(breaker.state(), BreakerState::Closed);
+}
+
+#[test]
+fn check_async_panic() {
+    let failed = spawn_async(|| -> i32 { panic!("boom") }).apply(|x| x + 1);
+    let payload = panic::catch_unwind(panic::AssertUnwindSafe(|| failed.take())).unwrap_err();
+    assert_eq!(*payload.downcast::<&str>().unwrap(), "boom");
+    let shared = enter(|scope| {
+        scope.spawn_async(|| -> i32 { panic!("scoped boom") }).share()
+    });
+    let chained = shared.apply(|x| *x);
+    assert!(panic::catch_unwind(panic::AssertUnwindSafe(|| chained.take())).is_err());
+    assert!(panic::catch_unwind(panic::AssertUnwindSafe(|| *shared.get())).is_err());
+}
+
+#[test]
+fn check_callback_panic_isolation() {
+    let (promise, future) = Promise::new();
+    let future = future.share();
+    let (tx, rx) = channel();
+    future.apply(|_| panic!("first subscriber"));
+    future.apply(move |x| tx.send(*x).unwrap());
+    assert!(panic::catch_unwind(panic::AssertUnwindSafe(|| promise.set(3))).is_err());
+    assert_eq!(rx.recv().unwrap(), 3);
+}
+
+#[test]
+fn check_subscribe() {
+    let (tx, rx) = channel();
+    let (promise, future) = Promise::new();
+    let shared = spawn_async(move || 5).share();
+    {
+        let tx = tx.clone();
+        future.on_complete(move |x| tx.send(x).unwrap());
+    }
+    shared.subscribe(move |x| tx.send(*x).unwrap());
+    promise.set(2);
+    let mut received = vec![rx.recv().unwrap(), rx.recv().unwrap()];
+    received.sort();
+    assert_eq!(received, vec![2, 5]);
+}
+
+#[test]
+fn check_try_get() {
+    let (promise, future) = Promise::new();
+    let future = future.share();
+    assert!(future.try_get().is_none());
+    promise.set(8);
+    assert_eq!(future.try_get(), Some(&8));
+    assert_eq!(future.clone().try_get(), Some(&8));
+}
+
+#[test]
+fn check_get_arc() {
+    let (promise, future) = Promise::new();
+    let shared = future.share();
+    thread::spawn(move || {
+        promise.set(RefCell::new(vec![1, 2]));
+    });
+    let value = shared.get_arc();
+    value.borrow_mut().push(3);
+    assert_eq!(*shared.get().borrow(), vec![1, 2, 3]);
+    drop(shared);
+    assert_eq!(value.borrow().len(), 3);
+}
+
+#[test]
+fn check_shared_panic_message() {
+    let message = |payload: PanicPayload| *payload.downcast::<String>().unwrap();
+    let shared = spawn_async(|| -> i32 { panic!("producer failed") }).share();
+    let payload = panic::catch_unwind(panic::AssertUnwindSafe(|| *shared.get())).unwrap_err();
+    assert_eq!(message(payload), "producer failed");
+    let payload = panic::catch_unwind(panic::AssertUnwindSafe(|| shared.get_arc())).unwrap_err();
+    assert_eq!(message(payload), "producer failed");
+    let mapped = shared.apply(|x| x + 1);
+    let payload = panic::catch_unwind(panic::AssertUnwindSafe(|| mapped.take())).unwrap_err();
+    assert_eq!(message(payload), "producer failed");
+    // and back as a plain future
+    let future = shared.try_unshare().ok().unwrap();
+    let payload = panic::catch_unwind(panic::AssertUnwindSafe(|| future.take())).unwrap_err();
+    assert_eq!(message(payload), "producer failed");
+}
+
+#[test]
+fn check_try_set() {
+    let (promise, future) = Promise::new();
+    let promise = Arc::new(promise);
+    let results = enter(|scope| {
+        let futures: Vec<_> = (0..4).map(|i| {
+            let promise = promise.clone();
+            scope.spawn_async(move || promise.try_set(i))
+        }).collect();
+        futures.into_iter().map(|f| f.take()).collect::<Vec<_>>()
+    });
+    assert_eq!(results.iter().filter(|r| r.is_ok()).count(), 1);
+    let winner = future.take();
+    assert!(results.iter().all(|r| *r != Err(winner)));
+}
+
+#[test]
+fn check_set_with() {
+    let (promise, future) = Promise::new();
+    drop(future);
+    promise.set_with(|| -> i32 { panic!("nobody is listening") });
+    let (promise, future) = Promise::new();
+    let (tx, rx) = channel();
+    future.on_complete(move |x| tx.send(x).unwrap());
+    promise.set_with(|| 3);
+    assert_eq!(rx.recv().unwrap(), 3);
+    let (promise, future) = Promise::new();
+    promise.set_with(|| 4);
+    assert_eq!(future.take(), 4);
+}
+
+#[test]
+fn check_complete_with() {
+    let (promise, future) = Promise::new();
+    promise.complete_with(spawn_async(|| 6).apply(|x| x * 7));
+    assert_eq!(future.take(), 42);
+    let (promise, future) = Promise::<i32>::new();
+    promise.complete_with(spawn_async(|| panic!("forwarded")));
+    assert!(panic::catch_unwind(panic::AssertUnwindSafe(|| future.take())).is_err());
+}
+
+#[test]
+fn check_consumers_gone() {
+    let (promise, future) = Promise::<i32>::new();
+    let (tx, rx) = channel();
+    promise.on_consumers_gone(move || tx.send(()).unwrap());
+    let shared = future.share();
+    let other = shared.clone();
+    drop(shared);
+    assert!(rx.try_recv().is_err());
+    drop(other);
+    rx.recv().unwrap();
+    let (promise, future) = Promise::<i32>::new();
+    let chained = future.apply(|x| x + 1);
+    drop(chained);
+    let called = Arc::new(AtomicI64::new(0));
+    let counter = called.clone();
+    promise.on_consumers_gone(move || {counter.fetch_add(1, Ordering::SeqCst);});
+    assert_eq!(called.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn check_map2() {
+    let left = spawn_async(|| {
+        thread::sleep(time::Duration::from_millis(5));
+        "answer"
+    });
+    let right = spawn_async(|| 42);
+    assert_eq!(left.map2(right, |l, r| format!("{} {}", l, r)).take(), "answer 42");
+}
+
+#[test]
+fn check_try_join_all() {
+    let ok = try_join_all((0..3).map(|i| spawn_async(move || Ok::<i32, String>(i * 2))));
+    assert_eq!(ok.take(), Ok(vec![0, 2, 4]));
+    let (_slow, pending) = Promise::<Result<i32, String>>::new();
+    let failed = try_join_all(vec![pending, spawn_async(|| Err("failed".to_string()))]);
+    assert_eq!(failed.take(), Err("failed".to_string()));
+
+    // the continuation completes another input, which takes the join's lock again
+    let (first, first_future) = Promise::<Result<i32, &str>>::new();
+    let (second, second_future) = Promise::new();
+    let joined = try_join_all(vec![first_future, second_future]).apply(move |result| {
+        second.set(Ok(1));
+        result
+    });
+    first.set(Err("failed"));
+    assert_eq!(joined.take(), Err("failed"));
+}
+
+#[test]
+fn check_traverse() {
+    assert_eq!(traverse(0..1000, |x| Future::new(x + 1)).take().len(), 1000);
+    let running = Arc::new(AtomicI64::new(0));
+    let peak = Arc::new(AtomicI64::new(0));
+    let squares = {
+        let running = running.clone();
+        let peak = peak.clone();
+        traverse_limited(vec![3, 1, 2, 5, 4], 2, move |x: i64| {
+            let running = running.clone();
+            let peak = peak.clone();
+            let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+            peak.fetch_max(now, Ordering::SeqCst);
+            spawn_async(move || {
+                thread::sleep(time::Duration::from_millis(x as u64));
+                running.fetch_sub(1, Ordering::SeqCst);
+                x * x
+            })
+        })
+    };
+    assert_eq!(squares.take(), vec![9, 1, 4, 25, 16]);
+    assert!(peak.load(Ordering::SeqCst) <= 2);
+}
+
+#[test]
+fn check_first_ok() {
+    let (_slow, pending) = Promise::<Result<i32, &str>>::new();
+    let hedged = first_ok(vec![pending, Future::new(Err("miss")), spawn_async(|| Ok(3))]);
+    assert_eq!(hedged.take(), Ok(3));
+    let failed = first_ok(vec![spawn_async(|| Err::<i32, _>("first")), Future::new(Err("second"))]);
+    assert_eq!(failed.take(), Err(vec!["first", "second"]));
+
+    let (first, first_future) = Promise::<Result<i32, &str>>::new();
+    let (second, second_future) = Promise::new();
+    let hedged = first_ok(vec![first_future, second_future]).apply(move |result| {
+        second.set(Err("late"));
+        result
+    });
+    first.set(Ok(1));
+    assert_eq!(hedged.take(), Ok(1));
+}
+
+#[test]
+fn check_recover() {
+    let failed = spawn_async(|| "12x".parse::<i32>());
+    assert_eq!(failed.recover(|_| -1).take(), -1);
+    assert_eq!(Future::new("12".parse::<i32>()).unwrap_or(0).take(), 12);
+    assert_eq!(Future::new(Err::<i32, ()>(())).unwrap_or(7).take(), 7);
+}
+
+#[test]
+fn check_inspect() {
+    let (tx, rx) = channel();
+    let result = spawn_async(|| 2)
+        .inspect(move |x| tx.send(*x).unwrap())
+        .apply(|x| x * 10);
+    assert_eq!(result.take(), 20);
+    assert_eq!(rx.recv().unwrap(), 2);
+}
+
+#[test]
+fn check_flatten() {
+    let nested = spawn_async(|| spawn_async(|| 5));
+    assert_eq!(nested.flatten().take(), 5);
+    let nested = Future::new(Future::new("ready"));
+    assert_eq!(nested.flatten().take(), "ready");
+}
+
+#[test]
+fn check_fuse() {
+    let (promise, future) = Promise::new();
+    let mut fused = future.fuse();
+    assert!(!fused.is_ready());
+    promise.set(1);
+    assert!(fused.is_ready());
+    assert_eq!(fused.take(), Some(1));
+    fused.wait();
+    fused.wait();
+    assert!(fused.is_terminated());
+    assert_eq!(fused.take(), None);
+}
+
+#[test]
+fn check_box_future() {
+    let offset = 10;
+    let pending: Vec<BoxFuture<i32>> = vec![
+        Future::new(1),
+        spawn_async(|| 2).apply(move |x| x + offset),
+        spawn_async(|| "3").apply(|s| s.parse().unwrap()),
+        Future::new(4).then(|x| spawn_async(move || x * 2))
+    ];
+    let values: Vec<_> = pending.into_iter().map(|f| f.take()).collect();
+    assert_eq!(values, vec![1, 12, 3, 8]);
+}
+
+#[test]
+fn check_either() {
+    let pick = |fast: bool| -> Future<Either<i32, String>> {
+        if fast {
+            Future::new(1).left_future()
+        } else {
+            spawn_async(|| "slow".to_string()).right_future()
+        }
+    };
+    assert_eq!(pick(true).take(), Either::Left(1));
+    assert_eq!(pick(false).take().right(), Some("slow".to_string()));
+    let (_never, pending) = Promise::<i32>::new();
+    assert_eq!(select(pending, spawn_async(|| "done")).take(), Either::Right("done"));
+}
+
+#[test]
+fn check_on_drop() {
+    let dropped = Arc::new(AtomicI64::new(0));
+    let (promise, future) = Promise::<i32>::new();
+    {
+        let dropped = dropped.clone();
+        future.on_drop(move || {dropped.fetch_add(1, Ordering::SeqCst);});
+    }
+    let taken = thread::spawn(move || future.take());
+    promise.set(1);
+    assert_eq!(taken.join().unwrap(), 1);
+    let (_promise, future) = Promise::<i32>::new();
+    {
+        let dropped = dropped.clone();
+        future.on_drop(move || {dropped.fetch_add(10, Ordering::SeqCst);});
+    }
+    drop(future);
+    assert_eq!(dropped.load(Ordering::SeqCst), 10);
+}
+
+#[cfg(feature = "sim")]
+#[test]
+fn check_simulation() {
+    use sim::Simulation;
+    use std::sync::Mutex;
+
+    let run = |seed| {
+        let sim = Simulation::new(seed);
+        let log = Arc::new(Mutex::new(Vec::new()));
+        for i in 0..5 {
+            let log = log.clone();
+            sim.spawn_async(move || log.lock().unwrap().push(i));
+        }
+        let timed = {
+            let log = log.clone();
+            sim.after(time::Duration::from_secs(30)).apply(move |_| log.lock().unwrap().push(100))
+        };
+        sim.run();
+        timed.take();
+        assert_eq!(sim.now(), time::Duration::from_secs(30));
+        let log = log.lock().unwrap().clone();
+        log
+    };
+    assert_eq!(run(7), run(7));
+    assert_eq!(*run(7).last().unwrap(), 100);
+}
+
+#[cfg(feature = "sim")]
+#[test]
+fn check_simulation_timeout() {
+    use sim::Simulation;
+
+    let sim = Simulation::new(1);
+    let slow = sim.after(time::Duration::from_secs(10)).apply(|_| 1);
+    let fast = sim.spawn_async(|| 2);
+    let timed_out = sim.timeout(slow, time::Duration::from_secs(5));
+    let in_time = sim.timeout(fast, time::Duration::from_secs(5));
+    let started = time::Instant::now();
+    sim.run();
+    assert!(started.elapsed() < time::Duration::from_secs(1));
+    assert_eq!(timed_out.take(), None);
+    assert_eq!(in_time.take(), Some(2));
+    assert_eq!(sim.now(), time::Duration::from_secs(10));
+}
+
+#[cfg(loom)]
+#[test]
+fn check_loom_spinlock() {
+    loom::model(|| {
+        let lock = loom::sync::Arc::new(Spinlock::new(0));
+        let other = lock.clone();
+        let incremented = loom::thread::spawn(move || {
+            *other.lock().unwrap() += 1;
+        });
+        *lock.lock().unwrap() += 1;
+        incremented.join().unwrap();
+        assert_eq!(*lock.lock().unwrap(), 2);
+    });
+}
+
+#[cfg(feature = "deadlock-detection")]
+#[test]
+fn check_self_deadlock_detection() {
+    let lock = Spinlock::new(0);
+    let guard = lock.lock();
+    assert!(panic::catch_unwind(panic::AssertUnwindSafe(|| lock.lock().is_some())).is_err());
+    drop(guard);
+    assert!(lock.lock().is_some());
+
+    let rw = ::spinlock::SpinRWLock::new(0);
+    let reader = rw.read().unwrap();
+    assert!(panic::catch_unwind(panic::AssertUnwindSafe(|| *rw.write().unwrap() += 1)).is_err());
+    drop(reader);
+    *rw.write().unwrap() += 1;
+    let writer = rw.write().unwrap();
+    assert!(panic::catch_unwind(panic::AssertUnwindSafe(|| *rw.read().unwrap())).is_err());
+    drop(writer);
+    assert_eq!(*rw.read().unwrap(), 1);
+}
+
+#[cfg(feature = "deadlock-detection")]
+#[test]
+fn check_lock_order_inversion() {
+    let first = Arc::new(Spinlock::new(()));
+    let second = Arc::new(::spinlock::SpinRWLock::new(()));
+    {
+        let _outer = first.lock();
+        let _inner = second.write().unwrap();
+    }
+    {
+        let _outer = first.lock();
+        let _inner = second.read().unwrap();
+    }
+    let (first2, second2) = (first.clone(), second.clone());
+    let inverted = thread::spawn(move || {
+        let _outer = second2.read().unwrap();
+        let _inner = first2.lock();
+    }).join();
+    assert!(inverted.is_err());
+    let _outer = first.lock();
+    let _inner = second.write().unwrap();
+}
+
+#[test]
+fn check_instrument() {
+    use instrument::{self, Instrument, TaskId};
+
+    static STARTED: AtomicI64 = AtomicI64::new(0);
+    static SET: AtomicI64 = AtomicI64::new(0);
+
+    struct Counting;
+
+    impl Instrument for Counting {
+        fn task_started(&self, _task: TaskId) {
+            STARTED.fetch_add(1, Ordering::SeqCst);
+        }
+
+        fn future_set(&self, _panicked: bool) {
+            SET.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    assert!(instrument::install(Box::new(Counting)).is_ok());
+    assert!(instrument::install(Box::new(Counting)).is_err());
+    let (started, set) = (STARTED.load(Ordering::SeqCst), SET.load(Ordering::SeqCst));
+    assert_eq!(spawn_async(|| 1).take(), 1);
+    assert!(STARTED.load(Ordering::SeqCst) > started);
+    assert!(SET.load(Ordering::SeqCst) > set);
+}
+
+#[cfg(feature = "metrics")]
+#[test]
+fn check_metrics() {
+    use metrics;
+
+    let before = metrics::snapshot();
+    let (promise, future) = Promise::new();
+    let future = future.apply(|x: i32| x + 1);
+    let waiting = spawn_async(move || future.take());
+    thread::sleep(time::Duration::from_millis(20));
+    promise.set(1);
+    assert_eq!(waiting.take(), 2);
+    let after = metrics::snapshot();
+    assert!(after.completed >= before.completed + 3);
+    assert!(after.callbacks_executed > before.callbacks_executed);
+    assert!(after.waits > before.waits);
+    assert!(after.average_wait > time::Duration::from_millis(0));
+}
+
+#[test]
+fn check_task_dump() {
+    use tasks::{self, TaskState};
+
+    let (promise, future) = Promise::<()>::new();
+    let (started, running) = channel();
+    let spawned_at = line!() + 1;
+    let task = spawn_async(move || {
+        started.send(()).unwrap();
+        future.take()
+    });
+    running.recv().unwrap();
+    let ours: Vec<_> = tasks::snapshot().into_iter()
+        .filter(|task| task.spawned_at.file() == file!() && task.spawned_at.line() == spawned_at)
+        .collect();
+    assert_eq!(ours.len(), 1);
+    assert_eq!(ours[0].state, TaskState::Running);
+    // merged across the registry shards, still oldest first
+    assert!(tasks::snapshot().windows(2).all(|pair| pair[0].id < pair[1].id));
+    assert!(tasks::dump().contains(&format!("{}:{}", file!(), spawned_at)));
+    promise.set(());
+    task.take();
+}
+
+#[test]
+fn check_thread_naming() {
+    use scope::enter_with;
+    use threads::ThreadConfig;
+
+    let names = enter_with(ThreadConfig::new().name_prefix("check-naming"), |scope| {
+        let first = scope.spawn_async(|| thread::current().name().map(String::from));
+        let second = scope.spawn_async(|| thread::current().name().map(String::from));
+        let mut names = vec![first.take().unwrap(), second.take().unwrap()];
+        names.sort();
+        names
+    });
+    assert_eq!(names, vec!["check-naming-0".to_string(), "check-naming-1".to_string()]);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn check_thread_affinity() {
+    use scope::enter_with;
+    use threads::ThreadConfig;
+
+    extern "C" {
+        fn sched_getcpu() -> i32;
+    }
+
+    let cpus = enter_with(ThreadConfig::new().affinity(&[0]), |scope| {
+        let futures: Vec<_> = (0..3).map(|_| scope.spawn_async(|| unsafe { sched_getcpu() })).collect();
+        futures.into_iter().map(|future| future.take()).collect::<Vec<_>>()
+    });
+    assert_eq!(cpus, vec![0, 0, 0]);
+}
+
+#[test]
+fn check_scope_joins_on_panic() {
+    let finished = AtomicI64::new(0);
+    let result = panic::catch_unwind(panic::AssertUnwindSafe(|| enter(|scope| {
+        scope.spawn(|| {
+            thread::sleep(time::Duration::from_millis(20));
+            finished.fetch_add(1, Ordering::SeqCst);
+        });
+        scope.spawn(|| panic!("worker"));
+        scope.spawn(|| {
+            thread::sleep(time::Duration::from_millis(20));
+            finished.fetch_add(1, Ordering::SeqCst);
+        });
+        panic!("body");
+    })));
+    assert_eq!(*result.unwrap_err().downcast::<&str>().unwrap(), "body");
+    assert_eq!(finished.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn check_try_enter() {
+    use scope::try_enter;
+
+    assert_eq!(try_enter(|scope| scope.spawn_async(|| 3).take()).unwrap(), 3);
+
+    let finished = AtomicI64::new(0);
+    let err = try_enter(|scope| {
+        scope.spawn(|| panic!("first"));
+        scope.spawn(|| {
+            thread::sleep(time::Duration::from_millis(20));
+            finished.fetch_add(1, Ordering::SeqCst);
+        });
+        scope.spawn(|| panic!("second"));
+    }).unwrap_err();
+    assert!(err.body.is_none());
+    let spawned: Vec<_> = err.spawned.into_iter().map(|p| *p.downcast::<&str>().unwrap()).collect();
+    assert_eq!(spawned, vec!["first", "second"]);
+    assert_eq!(finished.load(Ordering::SeqCst), 1);
+
+    let err = try_enter(|scope| {
+        scope.spawn(|| panic!("worker"));
+        panic!("body")
+    }).unwrap_err();
+    assert_eq!(*err.body.unwrap().downcast::<&str>().unwrap(), "body");
+    assert_eq!(err.spawned.len(), 1);
+}
+
+#[test]
+fn check_defer_order() {
+    use scope::DeferOrder;
+
+    let log = RefCell::new(Vec::new());
+    enter(|scope| {
+        scope.set_defer_order(DeferOrder::Lifo);
+        scope.defer(|| log.borrow_mut().push(1));
+        scope.defer(|| log.borrow_mut().push(2));
+        scope.run_deferred_now();
+        assert_eq!(*log.borrow(), vec![2, 1]);
+        scope.defer(|| log.borrow_mut().push(3));
+        scope.defer(|| log.borrow_mut().push(4));
+    });
+    assert_eq!(*log.borrow(), vec![2, 1, 4, 3]);
+
+    let ran = AtomicI64::new(0);
+    enter(|scope| {
+        scope.spawn(|| {
+            thread::sleep(time::Duration::from_millis(10));
+            ran.fetch_add(1, Ordering::SeqCst);
+        });
+        scope.run_deferred_now();
+        assert_eq!(ran.load(Ordering::SeqCst), 1);
+    });
+}
+
+#[test]
+fn check_child_scope() {
+    let log = std::sync::Mutex::new(Vec::new());
+    enter(|scope| {
+        scope.spawn(|| {
+            thread::sleep(time::Duration::from_millis(30));
+            log.lock().unwrap().push("parent");
+        });
+        let local = 2;
+        let doubled = scope.child(|child| {
+            child.defer(|| log.lock().unwrap().push("child cleanup"));
+            child.spawn(|| {
+                thread::sleep(time::Duration::from_millis(10));
+                log.lock().unwrap().push("child");
+            });
+            child.spawn_async(|| local * 2)
+        });
+        assert_eq!(doubled.take(), 4);
+        assert_eq!(*log.lock().unwrap(), vec!["child cleanup", "child"]);
+    });
+    assert_eq!(log.into_inner().unwrap(), vec!["child cleanup", "child", "parent"]);
+}
+
+#[test]
+fn check_current_scope() {
+    use scope::current_scope;
+
+    fn deep_in_a_library(counter: Arc<AtomicI64>) -> bool {
+        current_scope(|scope| match scope {
+            Some(scope) => {
+                scope.spawn(move || {
+                    thread::sleep(time::Duration::from_millis(10));
+                    counter.fetch_add(1, Ordering::SeqCst);
+                });
+                true
+            },
+            None => false
+        })
+    }
+
+    let counter = Arc::new(AtomicI64::new(0));
+    assert!(!deep_in_a_library(counter.clone()));
+    enter(|_| {
+        assert!(deep_in_a_library(counter.clone()));
+        assert_eq!(current_scope(|scope| scope.unwrap().spawn_async(|| 5).take()), 5);
+    });
+    assert_eq!(counter.load(Ordering::SeqCst), 1);
+    assert!(!deep_in_a_library(counter.clone()));
+}
+
+#[test]
+fn check_scope_concurrency_limit() {
+    let running = AtomicI64::new(0);
+    let peak = AtomicI64::new(0);
+    let threads = std::sync::Mutex::new(std::collections::HashSet::new());
+    let done = enter(|scope| {
+        scope.set_max_concurrency(Some(2));
+        let futures: Vec<_> = (0..6).map(|i| {
+            let (running, peak, threads) = (&running, &peak, &threads);
+            scope.spawn_async(move || {
+                let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                peak.fetch_max(now, Ordering::SeqCst);
+                threads.lock().unwrap().insert(thread::current().id());
+                thread::sleep(time::Duration::from_millis(10));
+                running.fetch_sub(1, Ordering::SeqCst);
+                i
+            })
+        }).collect();
+        futures.into_iter().map(|f| f.take()).sum::<i32>()
+    });
+    assert_eq!(done, 15);
+    assert!(peak.load(Ordering::SeqCst) <= 2);
+    assert!(threads.lock().unwrap().len() <= 2);
+}
+
+#[test]
+fn check_async_for_each() {
+    let running = AtomicI64::new(0);
+    let peak = AtomicI64::new(0);
+    let sum = AtomicI64::new(0);
+    enter(|scope| {
+        scope.async_for_each(1..=20, 3, |i| {
+            let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+            peak.fetch_max(now, Ordering::SeqCst);
+            thread::sleep(time::Duration::from_millis(2));
+            sum.fetch_add(i, Ordering::SeqCst);
+            running.fetch_sub(1, Ordering::SeqCst);
+        }).take();
+        assert_eq!(sum.load(Ordering::SeqCst), 210);
+
+        let failed = scope.async_for_each(0..4, 2, |i| assert!(i != 2));
+        assert!(panic::catch_unwind(panic::AssertUnwindSafe(|| failed.take())).is_err());
+    });
+    assert!(peak.load(Ordering::SeqCst) <= 3);
+}
+
+#[test]
+fn check_scope_cancel() {
+    // the scope only ends once the spinning worker notices the cancellation
+    enter(|scope| {
+        let canceled = scope.cancellation();
+        scope.spawn(move || {
+            while !canceled.is_canceled() {
+                thread::sleep(time::Duration::from_millis(1));
+            }
+        });
+        scope.child(|child| {
+            let failed = child.spawn_async(|| -> i32 { panic!("sibling failed") });
+            if panic::catch_unwind(panic::AssertUnwindSafe(|| failed.take())).is_err() {
+                scope.cancel();
+            }
+            assert!(child.is_canceled());
+        });
+        assert!(scope.is_canceled());
+    });
+}
+
+#[test]
+fn check_scope_panic_handler() {
+    let handled = std::sync::Mutex::new(Vec::new());
+    let spawned_at = enter(|scope| {
+        scope.set_panic_handler(|task, payload| {
+            handled.lock().unwrap().push((task.spawned_at.line(), *payload.downcast::<&str>().unwrap()));
+        });
+        let line = line!() + 1;
+        scope.spawn(|| panic!("logged"));
+        scope.spawn(|| ());
+        line
+    });
+    assert_eq!(handled.into_inner().unwrap(), vec![(spawned_at, "logged")]);
+}
+
+#[test]
+fn check_context_propagation() {
+    use context;
+
+    #[derive(Clone, Copy, PartialEq, Debug)]
+    struct TraceId(u64);
+
+    let (promise, future) = Promise::new();
+    let traced = context::with_value(TraceId(7), || {
+        future.apply(|x: i32| (x, context::get::<TraceId>()))
+    });
+    assert_eq!(context::get::<TraceId>(), None);
+    spawn_async(move || promise.set(1));
+    assert_eq!(traced.take(), (1, Some(TraceId(7))));
+
+    let nested = context::with_value(TraceId(1), || context::with_value("deadline", || {
+        (context::get::<TraceId>(), context::get::<&str>())
+    }));
+    assert_eq!(nested, (Some(TraceId(1)), Some("deadline")));
+}
+
+#[test]
+fn check_yield_points() {
+    use scope::Budget;
+    use yield_now_if_contended;
+
+    assert!(!yield_now_if_contended());
+    let order = std::sync::Mutex::new(Vec::new());
+    enter(|scope| {
+        scope.set_max_concurrency(Some(1));
+        let (queued, ready) = channel();
+        let order = &order;
+        scope.spawn(move || {
+            ready.recv().unwrap();
+            let mut budget = Budget::new(3);
+            let mut yielded = false;
+            for i in 0..6 {
+                order.lock().unwrap().push(i);
+                yielded |= budget.tick();
+            }
+            assert!(yielded);
+        });
+        scope.spawn(move || order.lock().unwrap().push(100));
+        queued.send(()).unwrap();
+    });
+    assert_eq!(order.into_inner().unwrap(), vec![0, 1, 2, 100, 3, 4, 5]);
+}
+
+#[test]
+fn check_scope_priorities() {
+    use scope::Priority;
+
+    let order = std::sync::Mutex::new(Vec::new());
+    enter(|scope| {
+        scope.set_max_concurrency(Some(1));
+        let (started, ready) = channel();
+        let order = &order;
+        scope.spawn(move || ready.recv().unwrap());
+        scope.spawn_with_priority(Priority::Background, move || order.lock().unwrap().push("background"));
+        scope.spawn(move || order.lock().unwrap().push("normal"));
+        let latency = scope.spawn_async_with_priority(Priority::Latency, move || order.lock().unwrap().push("latency"));
+        scope.spawn_with_priority(Priority::Latency, move || order.lock().unwrap().push("latency 2"));
+        started.send(()).unwrap();
+        latency.take();
+    });
+    assert_eq!(order.into_inner().unwrap(), vec!["latency", "latency 2", "normal", "background"]);
+}
+
+#[test]
+fn check_io_offload() {
+    use io;
+
+    let path = std::env::temp_dir().join(format!("threading-io-{}", std::process::id()));
+    io::write_file(path.clone(), b"offloaded".to_vec()).take().unwrap();
+    assert_eq!(io::read_file(&path).take().unwrap(), b"offloaded");
+    std::fs::remove_file(&path).unwrap();
+    assert!(io::read_file(&path).take().is_err());
+    let name = io::offload(|| thread::current().name().map(String::from)).take().unwrap();
+    assert!(name.starts_with("blocking-"));
+}
+
+#[test]
+fn check_mpsc_adapters() {
+    use channel::ReceiverExt;
+
+    let (sender, receiver) = channel();
+    let received = receiver.recv_future();
+    Future::new(1).send_to(sender.clone());
+    let (promise, future) = Promise::new();
+    future.send_to(sender);
+    let (msg, receiver) = received.take().unwrap();
+    assert_eq!(msg, 1);
+
+    let received = receiver.recv_future();
+    promise.set(2);
+    let (msg, receiver) = received.take().unwrap();
+    assert_eq!(msg, 2);
+    assert!(receiver.recv_future().take().is_err());
+
+    let (sender, receiver) = channel::<i32>();
+    let (promise, future) = Promise::new();
+    future.send_to(sender);
+    promise.set_panic(Box::new("boom"));
+    assert!(receiver.recv().is_err());
+}
+
+#[test]
+fn check_into_future() {
+    #[derive(Debug, PartialEq)]
+    struct Point {
+        x: i32,
+        y: i32
+    }
+
+    assert_eq!(Future::new(4).then(|x| Ready(x * 2)).take(), 8);
+    assert_eq!(Future::new(4).then(|x| spawn_async(move || x + 1)).take(), 5);
+    let point = Future::new(1).then(|x| Ready(Point{x, y: x + 1})).take();
+    assert_eq!(point, Point{x: 1, y: 2});
+    let parsed = Future::new("12").then(|s| Ready(s.parse::<i32>())).take();
+    assert_eq!(parsed, Ok(12));
+    let shared = Future::new(String::from("abc")).share();
+    assert_eq!(shared.then(|s| Ready(s.len())).take(), 3);
+    let future: Future<i32> = 7.into();
+    assert_eq!(future.then(|x| Future::from(Some(x))).take(), Some(7));
+}
+
+#[test]
+fn check_join_all_collect() {
+    use future::{JoinAll, IntoFuture};
+
+    let joined: JoinAll<_> = (0..5).map(|x| spawn_async(move || x * x)).collect();
+    assert_eq!(joined.take(), vec![0, 1, 4, 9, 16]);
+    let empty: JoinAll<i32> = Vec::new().into_iter().collect();
+    assert!(empty.take().is_empty());
+    let sum = Future::new(3)
+        .then(|n| (0..n).map(Future::new).collect::<JoinAll<_>>())
+        .apply(|values| values.iter().sum::<i32>());
+    assert_eq!(sum.take(), 3);
+    let joined: JoinAll<_> = vec![Future::new(1), Future::new(2)].into_iter().collect();
+    assert_eq!(joined.into_future().take(), vec![1, 2]);
+}
+
+#[test]
+fn check_par_map() {
+    use parallel::ParMap;
+
+    let offset = 10;
+    let squares: Vec<_> = (0..100).par_map(|x| x * x + offset).collect();
+    assert_eq!(squares, (0..100).map(|x| x * x + offset).collect::<Vec<_>>());
+    let lengths: Vec<_> = vec!["a", "bcd", ""].into_iter().par_map(str::len).filter(|&n| n > 0).collect();
+    assert_eq!(lengths, vec![1, 3]);
+    let panicked = panic::catch_unwind(|| {
+        (0..4).par_map(|x| if x == 2 { panic!("bad item") } else { x }).count()
+    });
+    assert!(panicked.is_err());
+}
+
+#[test]
+fn check_parallel_sort() {
+    use parallel;
+
+    let mut seed = 12345u64;
+    let mut values: Vec<u64> = (0..50_000).map(|_| {
+        seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (seed >> 33) % 1000
+    }).collect();
+    let mut expected = values.clone();
+    expected.sort();
+    parallel::sort_with_cutoff(&mut values, 64);
+    assert_eq!(values, expected);
+
+    let mut sorted: Vec<_> = (0..10_000).collect();
+    parallel::sort(&mut sorted);
+    assert!(sorted.windows(2).all(|w| w[0] <= w[1]));
+    let mut empty: Vec<i32> = Vec::new();
+    parallel::sort_with_cutoff(&mut empty, 0);
+}
+
+#[test]
+fn check_for_each_chunk() {
+    use parallel;
+
+    let mut values: Vec<u64> = (0..10_000).collect();
+    let calls = AtomicI64::new(0);
+    parallel::for_each_chunk(&mut values, |chunk| {
+        calls.fetch_add(1, Ordering::SeqCst);
+        // a skewed head makes the first share slow, the rest has to be stolen from it
+        if chunk[0] < 100 {
+            thread::sleep(time::Duration::from_millis(1));
+        }
+        chunk.iter_mut().for_each(|x| *x *= 2);
+    });
+    assert_eq!(values, (0..10_000).map(|x| x * 2).collect::<Vec<_>>());
+    assert!(calls.load(Ordering::SeqCst) > 1);
+
+    let mut single = vec![1];
+    parallel::for_each_chunk(&mut single, |chunk| chunk[0] += 1);
+    assert_eq!(single, vec![2]);
+    parallel::for_each_chunk(&mut Vec::<i32>::new(), |_| unreachable!());
+}
+
+#[test]
+fn check_parallel_fold() {
+    use parallel;
+
+    let sum = parallel::fold(1..=1000u64, || 0, |acc, x| acc + x, |a, b| a + b);
+    assert_eq!(sum, 500_500);
+    let histogram = parallel::fold((0..1000).map(|x| x % 4), || vec![0; 4], |mut acc, x| {
+        acc[x] += 1;
+        acc
+    }, |a, b| a.iter().zip(b).map(|(x, y)| x + y).collect());
+    assert_eq!(histogram, vec![250; 4]);
+    // concatenation is associative but not commutative, so order has to survive
+    let joined = parallel::fold((0..50).map(|x| x.to_string()), String::new, |acc, x| acc + &x, |a, b| a + &b);
+    assert_eq!(joined, (0..50).map(|x| x.to_string()).collect::<String>());
+    assert_eq!(parallel::fold(Vec::<i32>::new(), || 7, |acc, x| acc + x, |a, b| a + b), 7);
+}
+
+#[test]
+fn check_via_executor() {
+    use future::Job;
+    use threads::ThreadConfig;
+
+    let (promise, future) = Promise::new();
+    let name = future
+        .via(ThreadConfig::new().name_prefix("via"))
+        .apply(|x: i32| (x, thread::current().name().map(String::from)));
+    promise.set(1);
+    let (value, name) = name.take();
+    assert_eq!(value, 1);
+    assert!(name.unwrap().starts_with("via-"));
+
+    let queue: Arc<std::sync::Mutex<Vec<Job>>> = Arc::default();
+    let ran = Arc::new(AtomicI64::new(0));
+    let executor = {
+        let queue = queue.clone();
+        move |job| queue.lock().unwrap().push(job)
+    };
+    let later = {
+        let ran = ran.clone();
+        Future::new(2).via(executor).apply(move |x| ran.fetch_add(x, Ordering::SeqCst))
+    };
+    assert_eq!(ran.load(Ordering::SeqCst), 0);
+    let jobs: Vec<_> = queue.lock().unwrap().drain(..).collect();
+    jobs.into_iter().for_each(|job| job());
+    later.wait();
+    assert_eq!(ran.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn check_wait_deadline() {
+    let (slow_promise, slow) = Promise::<i32>::new();
+    let (fast_promise, fast) = Promise::new();
+    let deadline = time::Instant::now() + time::Duration::from_millis(50);
+    let setter = spawn_async(move || fast_promise.set(1));
+    assert!(fast.wait_deadline(deadline));
+    assert!(!slow.wait_deadline(deadline));
+    assert!(time::Instant::now() >= deadline);
+    assert!(!slow.wait_timeout(time::Duration::from_millis(1)));
+    slow_promise.set(2);
+    assert!(slow.wait_deadline(deadline));
+    setter.take();
+    assert_eq!(fast.take() + slow.take(), 3);
+
+    let (promise, future) = Promise::new();
+    let shared = future.share();
+    assert!(!shared.wait_timeout(time::Duration::from_millis(1)));
+    promise.set(3);
+    assert!(shared.wait_deadline(time::Instant::now()));
+}
+
+#[test]
+fn check_try_unshare() {
+    let shared = Future::new(vec![1, 2]).share();
+    let clone = shared.clone();
+    assert_eq!(clone.get().len(), 2);
+    let shared = shared.try_unshare().err().unwrap();
+    drop(clone);
+    let arc = shared.get_arc();
+    let shared = shared.try_unshare().err().unwrap();
+    drop(arc);
+    assert_eq!(shared.try_unshare().ok().unwrap().take(), vec![1, 2]);
+
+    let (promise, future) = Promise::new();
+    let shared = future.share();
+    let gone = Arc::new(AtomicI64::new(0));
+    {
+        let gone = gone.clone();
+        promise.on_consumers_gone(move || {gone.fetch_add(1, Ordering::SeqCst);});
+    }
+    let future = shared.try_unshare().ok().unwrap();
+    assert_eq!(gone.load(Ordering::SeqCst), 0);
+    promise.set(String::from("later"));
+    assert_eq!(future.take(), "later");
+}
+
+#[test]
+fn check_try_unshare_with_running_callback() {
+    let (promise, future) = Promise::new();
+    let shared = future.share();
+    let (started_tx, started_rx) = channel();
+    let (resume_tx, resume_rx) = channel::<()>();
+    shared.subscribe(move |value: &Vec<i32>| {
+        started_tx.send(()).unwrap();
+        resume_rx.recv().unwrap();
+        // still alive, the handle below couldn't take it away
+        assert_eq!(*value, vec![1, 2]);
+    });
+    thread::scope(|s| {
+        let setter = s.spawn(move || promise.set(vec![1, 2]));
+        started_rx.recv().unwrap();
+        let shared = shared.try_unshare().err().unwrap();
+        resume_tx.send(()).unwrap();
+        setter.join().unwrap();
+        assert_eq!(shared.try_unshare().ok().unwrap().take(), vec![1, 2]);
+    });
+}
+
+#[test]
+fn check_promise_diagnostics() {
+    let (promise, future) = Promise::new();
+    assert!(promise.is_pending());
+    assert_eq!(promise.pending_callbacks(), 0);
+    let shared = future.share();
+    let doubled = shared.apply(|x| x * 2);
+    let tripled = shared.apply(|x| x * 3);
+    assert_eq!(promise.pending_callbacks(), 2);
+    let waiter = {
+        let shared = shared.clone();
+        thread::spawn(move || *shared.get())
+    };
+    while promise.waiters() == 0 {
+        thread::yield_now();
+    }
+    assert_eq!(promise.waiters(), 1);
+    assert!(promise.try_set(1).is_ok());
+    assert!(!promise.is_pending());
+    assert_eq!(promise.pending_callbacks(), 0);
+    assert_eq!(waiter.join().unwrap() + doubled.take() + tripled.take(), 6);
+    assert_eq!(promise.waiters(), 0);
+}
+
+#[test]
+fn check_race_promise() {
+    use future::RacePromise;
+
+    let (promise, future) = RacePromise::new();
+    let producers: Vec<_> = (0..4).map(|idx| {
+        let promise = promise.clone();
+        spawn_async(move || {
+            thread::sleep(time::Duration::from_millis(idx * 10));
+            promise.set(idx)
+        })
+    }).collect();
+    let winner = future.take();
+    let won: Vec<_> = producers.into_iter().map(|producer| producer.take()).collect();
+    assert_eq!(won.iter().filter(|&&won| won).count(), 1);
+    assert!(won[winner as usize]);
+    assert!(!promise.is_pending());
+    assert!(!promise.set(10));
+}
+
+#[test]
+fn check_raw_promise() {
+    use future::complete_raw;
+    use std::ffi::c_void;
+
+    // stands in for a C library taking a callback and its user data
+    fn c_api(callback: unsafe extern "C" fn(*mut c_void, i32), user_data: *mut c_void) {
+        unsafe { callback(user_data, 42) }
+    }
+
+    let (promise, future) = Promise::<i32>::new();
+    c_api(complete_raw::<i32>, promise.into_raw());
+    assert_eq!(future.take(), 42);
+
+    let (promise, future) = Promise::<String>::new();
+    let raw = promise.into_raw();
+    unsafe { Promise::<String>::from_raw(raw) }.set(String::from("back"));
+    assert_eq!(future.take(), "back");
+}
+
+#[test]
+fn check_upstream_cancellation() {
+    let gone = Arc::new(AtomicI64::new(0));
+    let watch = |promise: &Promise<i32>| {
+        let gone = gone.clone();
+        promise.on_consumers_gone(move || {gone.fetch_add(1, Ordering::SeqCst);});
+    };
+
+    let (promise, future) = Promise::new();
+    watch(&promise);
+    let chained = future.apply(|x| x + 1).then(|x| Future::new(x * 2)).apply(|x| x - 1);
+    assert_eq!(gone.load(Ordering::SeqCst), 0);
+    drop(chained);
+    assert_eq!(gone.load(Ordering::SeqCst), 1);
+    drop(promise);
+
+    // the other side of map2 is held by the pending callback, so only the first is released
+    let (left_promise, left) = Promise::new();
+    let (right_promise, right) = Promise::new();
+    watch(&left_promise);
+    watch(&right_promise);
+    let sum = left.map2(right, |l, r| l + r);
+    drop(sum);
+    assert_eq!(gone.load(Ordering::SeqCst), 2);
+    drop(left_promise);
+    assert_eq!(gone.load(Ordering::SeqCst), 3);
+
+    // through the future returned from then
+    let (inner_promise, inner) = Promise::new();
+    watch(&inner_promise);
+    let outer = Future::new(1).then(move |_| inner);
+    drop(outer);
+    assert_eq!(gone.load(Ordering::SeqCst), 4);
+
+    let (promise, future) = Promise::new();
+    watch(&promise);
+    let kept = future.apply(|x| x * 3);
+    promise.set(2);
+    assert_eq!(kept.take(), 6);
+    assert_eq!(gone.load(Ordering::SeqCst), 4);
+}
+
+#[test]
+fn check_dropped_promise_frees_chain() {
+    let token = Arc::new(1);
+
+    // nothing upstream of a pending chain outlives its producer
+    let (promise, future) = Promise::<i32>::new();
+    let held = token.clone();
+    promise.on_consumers_gone(move || drop(held));
+    let captured = token.clone();
+    let chained = future.apply(move |x| x + *captured).apply(|x| x * 2);
+    assert_eq!(Arc::strong_count(&token), 3);
+    drop(promise);
+    assert_eq!(Arc::strong_count(&token), 1);
+    drop(chained);
+
+    // nor past the value being set
+    let (promise, future) = Promise::new();
+    let held = token.clone();
+    promise.on_consumers_gone(move || drop(held));
+    promise.set(2);
+    assert_eq!(Arc::strong_count(&token), 1);
+    assert_eq!(future.take(), 2);
+}
+
+#[test]
+fn check_catch_continuations() {
+    let recovered = spawn_async(|| -> i32 { panic!("stage failed") })
+        .apply(|x| x + 1)
+        .apply_catch(|result| result.unwrap_or(-1));
+    assert_eq!(recovered.take(), -1);
+    let passed = Future::new(2).apply_catch(|result| result.map_or(0, |x| x * 10));
+    assert_eq!(passed.take(), 20);
+
+    let retried = spawn_async(|| -> i32 { panic!("first try") })
+        .then_catch(|result| match result {
+            Ok(value) => Future::new(value),
+            Err(_) => spawn_async(|| 5)
+        });
+    assert_eq!(retried.take(), 5);
+    let message = spawn_async(|| -> i32 { panic!("with message") })
+        .apply(|x| x * 2)
+        .then_catch(|result| Ready(*result.unwrap_err().downcast::<&str>().unwrap()));
+    assert_eq!(message.take(), "with message");
+
+    let rethrown = Future::new(1).apply_catch(|_| -> i32 { panic!("again") });
+    assert!(panic::catch_unwind(panic::AssertUnwindSafe(|| rethrown.take())).is_err());
+}
+
+#[test]
+fn check_callback_order() {
+    use scope::Priority;
+    use std::sync::Mutex;
+
+    let (promise, future) = Promise::new();
+    let shared = future.share();
+    let order = Arc::new(Mutex::new(Vec::new()));
+    let push = |label: &'static str| {
+        let order = order.clone();
+        move |_: &i32| order.lock().unwrap().push(label)
+    };
+    shared.subscribe(push("first"));
+    shared.subscribe_with_priority(Priority::Background, push("cleanup"));
+    shared.subscribe(push("second"));
+    shared.subscribe_with_priority(Priority::Latency, push("urgent"));
+    shared.subscribe_with_priority(Priority::Latency, push("urgent too"));
+    promise.set(1);
+    assert_eq!(*order.lock().unwrap(), vec!["urgent", "urgent too", "first", "second", "cleanup"]);
+}
+
+#[test]
+fn check_callback_dispatcher() {
+    use future::{set_callback_dispatch, CallbackDispatch};
+
+    set_callback_dispatch(CallbackDispatch::Dispatcher);
+    let (promise, future) = Promise::new_static();
+    let ran_on = future.apply(|x: i32| (x, thread::current().name().map(String::from)));
+    let (borrowed_promise, borrowed) = Promise::new();
+    let inline_on = borrowed.apply(|_: i32| thread::current().id());
+    promise.set(1);
+    borrowed_promise.set(2);
+    set_callback_dispatch(CallbackDispatch::Inline);
+    let (value, name) = ran_on.take();
+    assert_eq!(value, 1);
+    assert!(name.unwrap().starts_with("callbacks-"));
+    assert_eq!(inline_on.take(), thread::current().id());
+}
+
+#[test]
+fn check_subscribe_on() {
+    use threads::ThreadConfig;
+
+    let (promise, future) = Promise::new();
+    let shared = future.share();
+    let (tx, rx) = channel();
+    for prefix in &["slow", "fast"] {
+        let tx = tx.clone();
+        let delay = if *prefix == "slow" { 50 } else { 0 };
+        shared.subscribe_on(ThreadConfig::new().name_prefix(prefix), move |x: &i32| {
+            thread::sleep(time::Duration::from_millis(delay));
+            tx.send((*x, thread::current().name().map(String::from).unwrap())).unwrap();
+        });
+    }
+    promise.set(4);
+    assert_eq!(rx.recv().unwrap(), (4, String::from("fast-0")));
+    assert_eq!(rx.recv().unwrap(), (4, String::from("slow-0")));
+}
+
+#[test]
+fn check_weighted_semaphore() {
+    use semaphore::Semaphore;
+    use std::sync::Mutex;
+
+    let semaphore = Semaphore::new(4);
+    semaphore.acquire(3);
+    assert!(!semaphore.try_acquire(2));
+    assert!(semaphore.try_acquire(1));
+    assert_eq!(semaphore.available(), 0);
+
+    let order = Mutex::new(Vec::new());
+    enter(|scope| {
+        let (semaphore, order) = (&semaphore, &order);
+        scope.spawn(move || {
+            semaphore.acquire(4);
+            order.lock().unwrap().push(4);
+            semaphore.release(4);
+        });
+        thread::sleep(time::Duration::from_millis(20));
+        // queued behind the large acquire even though it would fit earlier
+        scope.spawn(move || {
+            semaphore.acquire(1);
+            order.lock().unwrap().push(1);
+            semaphore.release(1);
+        });
+        thread::sleep(time::Duration::from_millis(20));
+        semaphore.release(1);
+        assert!(!semaphore.try_acquire(1));
+        semaphore.release(3);
+    });
+    assert_eq!(*order.lock().unwrap(), vec![4, 1]);
+    assert_eq!(semaphore.available(), 4);
+}
+
+#[test]
+fn check_rw_semaphore() {
+    use semaphore::RwSemaphore;
+
+    let semaphore = RwSemaphore::new();
+    semaphore.acquire_read();
+    assert!(semaphore.try_acquire_read());
+    assert!(!semaphore.try_acquire_write());
+    let writer = semaphore.write_async();
+    // readers arriving after a waiting writer queue up behind it
+    let late_reader = semaphore.read_async();
+    assert!(!semaphore.try_acquire_read());
+    semaphore.release_read();
+    assert!(!writer.wait_timeout(time::Duration::from_millis(5)));
+    semaphore.release_read();
+    writer.take();
+    assert!(!late_reader.wait_timeout(time::Duration::from_millis(5)));
+
+    let blocked = {
+        let semaphore = semaphore.clone();
+        spawn_async(move || {
+            semaphore.acquire_write();
+            semaphore.release_write();
+        })
+    };
+    semaphore.release_write();
+    late_reader.take();
+    assert!(!blocked.wait_timeout(time::Duration::from_millis(5)));
+    semaphore.release_read();
+    blocked.take();
+
+    // an abandoned acquisition doesn't keep the permit
+    assert!(semaphore.try_acquire_write());
+    drop(semaphore.read_async());
+    semaphore.release_write();
+    assert!(semaphore.try_acquire_write());
+}
+
+#[test]
+fn check_semaphore_timeout() {
+    use semaphore::Semaphore;
+
+    let semaphore = Semaphore::new(2);
+    assert!(semaphore.try_acquire_for(2, time::Duration::from_millis(1)));
+    let started = time::Instant::now();
+    assert!(!semaphore.try_acquire_for(1, time::Duration::from_millis(20)));
+    assert!(started.elapsed() >= time::Duration::from_millis(20));
+    enter(|scope| {
+        let semaphore = &semaphore;
+        // a timed out large acquire must not keep blocking the queue
+        scope.spawn(move || assert!(!semaphore.try_acquire_for(3, time::Duration::from_millis(10))));
+        thread::sleep(time::Duration::from_millis(2));
+        scope.spawn(move || assert!(semaphore.try_acquire_for(1, time::Duration::from_secs(5))));
+        thread::sleep(time::Duration::from_millis(20));
+        semaphore.release(1);
+    });
+    assert_eq!(semaphore.available(), 0);
+}
+
+#[test]
+fn check_monitor() {
+    use monitor::Monitor;
+
+    let queue = Monitor::new(Vec::new());
+    let consumed = enter(|scope| {
+        let queue = &queue;
+        let consumer = scope.spawn_async(move || {
+            let mut items = queue.wait_while(queue.lock(), |items| items.len() < 3);
+            items.drain(..).sum::<i32>()
+        });
+        (1..=3).for_each(|item| queue.update(|items| items.push(item)));
+        consumer.take()
+    });
+    assert_eq!(consumed, 6);
+
+    let (guard, timed_out) = queue.wait_timeout_while(queue.lock(), time::Duration::from_millis(5), |items| items.is_empty());
+    assert!(timed_out && guard.is_empty());
+    drop(guard);
+    queue.lock().push(1);
+    queue.notify_one();
+    let (guard, timed_out) = queue.wait_timeout_while(queue.lock(), time::Duration::from_millis(5), |items| items.is_empty());
+    assert!(!timed_out && guard.len() == 1);
+}
+
+#[test]
+fn check_spin_condvar() {
+    use spinlock::SpinCondvar;
+
+    let state = Spinlock::new(0);
+    let changed = SpinCondvar::new();
+    let seen = enter(|scope| {
+        let (state, changed) = (&state, &changed);
+        let waiters: Vec<_> = (0..3).map(|_| scope.spawn_async(move || {
+            let guard = changed.wait_while(state.lock().unwrap(), |value| *value < 5);
+            *guard
+        })).collect();
+        (1..=5).for_each(|_| {
+            *state.lock().unwrap() += 1;
+            changed.notify_all();
+        });
+        waiters.into_iter().map(|waiter| waiter.take()).collect::<Vec<_>>()
+    });
+    assert_eq!(seen, vec![5; 3]);
+
+    let handoff = Spinlock::new(None);
+    enter(|scope| {
+        let (handoff, changed) = (&handoff, &changed);
+        scope.spawn(move || {
+            *handoff.lock().unwrap() = Some("ready");
+            changed.notify_one();
+        });
+        let guard = changed.wait_while(handoff.lock().unwrap(), |value| value.is_none());
+        assert_eq!(*guard, Some("ready"));
+    });
+}
+
+#[test]
+fn check_gate() {
+    use event::Gate;
+
+    let gate = Gate::new(true);
+    gate.wait();
+    gate.close();
+    let passed = AtomicI64::new(0);
+    enter(|scope| {
+        let (gate, passed) = (&gate, &passed);
+        (0..3).for_each(|_| scope.spawn(move || {
+            gate.wait();
+            passed.fetch_add(1, Ordering::SeqCst);
+        }));
+        thread::sleep(time::Duration::from_millis(20));
+        assert_eq!(passed.load(Ordering::SeqCst), 0);
+        gate.open();
+    });
+    assert_eq!(passed.load(Ordering::SeqCst), 3);
+    assert!(gate.is_open());
+    gate.wait();
+}
+
+#[test]
+fn check_event_pulse() {
+    use event::Event;
+
+    let event = Event::new();
+    let before = event.generation();
+    event.pulse();
+    // arriving after the pulse, but with the generation read before it
+    assert_eq!(event.wait_since(before), before + 1);
+    let woken = enter(|scope| {
+        let event = &event;
+        let waiter = scope.spawn_async(move || event.wait_for_next());
+        // the waiter may only start waiting after some of these
+        loop {
+            event.pulse();
+            if waiter.wait_timeout(time::Duration::from_millis(1)) {
+                break;
+            }
+        }
+        waiter.take()
+    });
+    assert!(woken > before + 1);
+    assert!(!event.wait_timeout(time::Duration::from_millis(1)));
+    event.signal();
+    assert!(event.generation() > woken);
+    event.wait();
+}
+
+#[test]
+fn check_event_wait_any() {
+    use event::Event;
+
+    let (data, shutdown) = (Event::new(), Event::new());
+    let woken = enter(|scope| {
+        let (data, shutdown) = (&data, &shutdown);
+        let waiter = scope.spawn_async(move || Event::wait_any(&[data, shutdown]));
+        thread::sleep(time::Duration::from_millis(10));
+        shutdown.signal();
+        waiter.take()
+    });
+    assert_eq!(woken, 1);
+    data.signal();
+    assert_eq!(Event::wait_any(&[&data, &shutdown]), 0);
+    Event::wait_all(&[&data, &shutdown]);
+
+    let events: Vec<_> = (0..3).map(|_| Event::new()).collect();
+    enter(|scope| {
+        let events = &events;
+        let all = scope.spawn_async(move || Event::wait_all(&events.iter().collect::<Vec<_>>()));
+        events.iter().rev().for_each(|event| {
+            assert!(!all.wait_timeout(time::Duration::from_millis(1)));
+            event.signal();
+        });
+        all.take();
+    });
+}
+
+#[test]
+fn check_select_waitables() {
+    use event::{Event, Timer, Waitable, select_waitables};
+    use channel::ReceiverExt;
+
+    let shutdown = Event::new();
+    let (sender, receiver) = channel::<i32>();
+    let message = receiver.recv_future();
+    let timer = Timer::after(time::Duration::from_millis(20));
+    let sources: [&dyn Waitable; 3] = [&shutdown, &message, &timer];
+    assert_eq!(select_waitables(&sources), 2);
+
+    let (promise, future) = Promise::new();
+    enter(|scope| {
+        scope.spawn(move || {
+            thread::sleep(time::Duration::from_millis(10));
+            sender.send(7).unwrap();
+        });
+        let sources: [&dyn Waitable; 3] = [&shutdown, &message, &future];
+        assert_eq!(select_waitables(&sources), 1);
+    });
+    promise.set(1);
+    shutdown.signal();
+    let sources: [&dyn Waitable; 3] = [&future, &shutdown, &Timer::after(time::Duration::from_secs(60))];
+    assert_eq!(select_waitables(&sources), 0);
+    assert_eq!(future.take(), 1);
+    assert_eq!(message.take().unwrap().0, 7);
+}
+
+#[test]
+fn check_select_channel() {
+    use event::{Timer, Waitable, select_waitables};
+    use channel::unbounded;
+    use std::sync::mpsc::{RecvTimeoutError, TryRecvError};
+
+    let (sender, receiver) = unbounded::<i32>();
+    let (promise, future) = Promise::<i32>::new();
+    for _ in 0..100 {
+        let sources: [&dyn Waitable; 3] = [&receiver, &future, &Timer::after(time::Duration::from_micros(10))];
+        assert_eq!(select_waitables(&sources), 2);
+    }
+    // the future forgets every watch once select returns
+    assert_eq!(promise.pending_callbacks(), 0);
+    assert_eq!(receiver.recv_timeout(time::Duration::from_millis(1)), Err(RecvTimeoutError::Timeout));
+
+    enter(|scope| {
+        let sender = sender.clone();
+        scope.spawn(move || {
+            thread::sleep(time::Duration::from_millis(10));
+            sender.send(7).unwrap();
+        });
+        let sources: [&dyn Waitable; 2] = [&future, &receiver];
+        assert_eq!(select_waitables(&sources), 1);
+    });
+    assert_eq!(receiver.try_recv(), Ok(7));
+    assert_eq!(receiver.try_recv(), Err(TryRecvError::Empty));
+
+    sender.send(8).unwrap();
+    drop(sender);
+    assert_eq!(receiver.recv(), Ok(8));
+    assert!(receiver.is_ready());
+    assert!(receiver.recv().is_err());
+    promise.set(1);
+    assert_eq!(future.take(), 1);
+}
+
+#[test]
+fn check_parker() {
+    use event::Parker;
+
+    let parker = Parker::new();
+    let unparker = parker.unparker();
+    assert!(!parker.park_timeout(time::Duration::from_millis(1)));
+    unparker.unpark();
+    unparker.unpark();
+    parker.park();
+    assert!(!parker.park_timeout(time::Duration::from_millis(1)));
+
+    enter(|scope| {
+        let unparker = unparker.clone();
+        scope.spawn(move || {
+            thread::sleep(time::Duration::from_millis(10));
+            unparker.unpark();
+        });
+        assert!(parker.park_timeout(time::Duration::from_secs(60)));
+    });
+}
+
+#[test]
+fn check_backoff() {
+    use backoff::Backoff;
+
+    let mut backoff = Backoff::new();
+    (0..100).for_each(|_| backoff.spin());
+    assert!(!backoff.is_completed());
+    while !backoff.is_completed() {
+        backoff.snooze();
+    }
+    (0..20).for_each(|_| backoff.snooze());
+    backoff.reset();
+    assert!(!backoff.is_completed());
+
+    let counter = AtomicI64::new(0);
+    enter(|scope| {
+        for _ in 0..4 {
+            let counter = &counter;
+            scope.spawn(move || {
+                let mut backoff = Backoff::new();
+                for _ in 0..1000 {
+                    let mut current = counter.load(Ordering::Relaxed);
+                    while let Err(seen) = counter.compare_exchange_weak(current, current + 1, Ordering::AcqRel, Ordering::Relaxed) {
+                        current = seen;
+                        backoff.spin();
+                    }
+                    backoff.reset();
+                }
+            });
+        }
+    });
+    assert_eq!(counter.load(Ordering::Relaxed), 4000);
+}
+
+#[test]
+fn check_spin_strategy() {
+    use spinlock::{SpinRWLock, SpinStrategy, SpinlockError, TryLockError};
+
+    // contending from another thread, deadlock detection rejects it on the holder's own
+    let lock = Spinlock::with_strategy(1, SpinStrategy::Bounded(3));
+    let guard = lock.lock().unwrap();
+    assert_eq!(thread::scope(|s| s.spawn(|| lock.try_lock().err()).join().unwrap()), Some(SpinlockError::WouldBlock));
+    // the blocking lock waits past the bound
+    thread::scope(|s| {
+        let waiter = s.spawn(|| *lock.lock().unwrap());
+        thread::sleep(time::Duration::from_millis(20));
+        drop(guard);
+        assert_eq!(waiter.join().unwrap(), 1);
+    });
+    assert_eq!(*lock.share(), 1);
+    assert!(lock.lock().is_none());
+    assert_eq!(lock.try_lock().err(), Some(SpinlockError::Shared));
+
+    let rw = SpinRWLock::with_strategy(2, SpinStrategy::Bounded(3));
+    let reader = rw.read().unwrap();
+    assert!(thread::scope(|s| s.spawn(|| matches!(rw.try_write(), Err(TryLockError::Exhausted))).join().unwrap()));
+    assert_eq!(thread::scope(|s| s.spawn(|| *rw.try_read().unwrap()).join().unwrap()), 2);
+    drop(reader);
+    let writer = rw.try_write().unwrap();
+    assert!(thread::scope(|s| s.spawn(|| rw.try_read().is_err()).join().unwrap()));
+    thread::scope(|s| {
+        let waiter = s.spawn(|| *rw.read().unwrap());
+        thread::sleep(time::Duration::from_millis(20));
+        drop(writer);
+        assert_eq!(waiter.join().unwrap(), 2);
+    });
+
+    let counter = SpinRWLock::with_strategy(0, SpinStrategy::Spin);
+    enter(|scope| {
+        for _ in 0..4 {
+            let counter = &counter;
+            scope.spawn(move || (0..1000).for_each(|_| *counter.write().unwrap() += 1));
+        }
+    });
+    assert_eq!(*counter.read().unwrap(), 4000);
+}
+
+#[test]
+fn check_rwlock_many_readers() {
+    use spinlock::SpinRWLock;
+
+    let lock = SpinRWLock::new(5);
+    let guards: Vec<_> = (0..40000).map(|_| lock.read().unwrap()).collect();
+    assert_eq!(lock.reader_count(), 40000);
+    assert!(!lock.is_write_locked());
+    assert!(guards.iter().all(|guard| **guard == 5));
+    drop(guards);
+    assert_eq!(lock.reader_count(), 0);
+    let writer = lock.write().unwrap();
+    assert!(lock.is_write_locked());
+    drop(writer);
+    assert!(!lock.is_write_locked());
+}
+
+#[test]
+fn check_rwlock_upgrade_downgrade() {
+    use spinlock::{SpinRWLock, SpinStrategy};
+
+    let lock = SpinRWLock::with_strategy(0, SpinStrategy::Bounded(3));
+    let elsewhere = |attempt: &(dyn Fn() -> bool + Sync)| thread::scope(|s| s.spawn(attempt).join().unwrap());
+    let upgradable = lock.upgradable_read().unwrap();
+    assert!(elsewhere(&|| *lock.read().unwrap() == 0));
+    assert!(elsewhere(&|| lock.try_upgradable_read().is_err()));
+    assert!(elsewhere(&|| lock.try_write().is_err()));
+    let mut writer = upgradable.upgrade();
+    *writer = 1;
+    let reader = writer.downgrade();
+    assert!(elsewhere(&|| lock.try_write().is_err()));
+    assert_eq!(*lock.read().unwrap(), 1);
+    drop(reader);
+    let upgradable = lock.write().unwrap().downgrade_to_upgradable();
+    assert_eq!(*upgradable.downgrade(), 1);
+    assert!(!lock.is_write_locked());
+    assert_eq!(lock.reader_count(), 0);
+
+    // every increment reads under an upgradable guard and writes after upgrading it,
+    // a writer sneaking in between would lose updates
+    let counter = SpinRWLock::new(0);
+    enter(|scope| {
+        for _ in 0..4 {
+            let counter = &counter;
+            scope.spawn(move || (0..500).for_each(|_| *counter.write().unwrap() += 1));
+            scope.spawn(move || (0..500).for_each(|_| {
+                let upgradable = counter.upgradable_read().unwrap();
+                let seen = *upgradable;
+                *upgradable.upgrade() = seen + 1;
+            }));
+        }
+    });
+    assert_eq!(*counter.read().unwrap(), 4000);
+}
+
+#[test]
+fn check_rwlock_exclusive_access() {
+    use spinlock::SpinRWLock;
+
+    let mut lock = SpinRWLock::new(vec![1]);
+    lock.get_mut().push(2);
+    enter(|scope| {
+        let lock = &lock;
+        scope.spawn(move || lock.write().unwrap().push(3));
+    });
+    lock.get_mut().push(4);
+    assert_eq!(lock.into_inner(), vec![1, 2, 3, 4]);
+}
+
+#[test]
+fn check_timed_lock() {
+    use spinlock::SpinRWLock;
+
+    let timeout = time::Duration::from_millis(20);
+    let lock = Spinlock::new(1);
+    let guard = lock.lock().unwrap();
+    let started = time::Instant::now();
+    assert!(thread::scope(|s| s.spawn(|| lock.try_lock_for(timeout).is_err()).join().unwrap()));
+    assert!(started.elapsed() >= timeout);
+    drop(guard);
+    assert_eq!(*lock.try_lock_for(timeout).unwrap(), 1);
+
+    let rw = SpinRWLock::new(2);
+    let reader = rw.read().unwrap();
+    assert!(thread::scope(|s| s.spawn(|| rw.try_write_for(timeout).is_err()).join().unwrap()));
+    assert_eq!(*rw.try_read_for(timeout).unwrap(), 2);
+    drop(reader);
+    let writer = rw.try_write_for(timeout).unwrap();
+    assert!(thread::scope(|s| s.spawn(|| rw.try_read_for(timeout).is_err()).join().unwrap()));
+    drop(writer);
+    assert!(!rw.is_write_locked());
+}
+
+#[test]
+fn check_owned_guards() {
+    use spinlock::SpinRWLock;
+
+    let lock = Arc::new(Spinlock::new(vec![1]));
+    let mut guard = lock.lock_owned().unwrap();
+    let (promise, future) = Promise::new();
+    let pushed = future.apply(move |value| {
+        guard.push(value);
+        drop(guard);
+    });
+    promise.set(2);
+    pushed.take();
+    assert_eq!(*lock.lock().unwrap(), vec![1, 2]);
+
+    let rw = Arc::new(SpinRWLock::new(0));
+    let mut writer = rw.write_owned().unwrap();
+    let reader = {
+        let rw = rw.clone();
+        thread::spawn(move || *rw.read_owned().unwrap())
+    };
+    thread::spawn(move || *writer = 5).join().unwrap();
+    assert_eq!(reader.join().unwrap(), 5);
+    let reader = rw.read_owned().unwrap();
+    assert!(thread::spawn(move || drop(reader)).join().is_ok());
+    assert!(rw.try_write_owned().is_ok());
+}
+
+#[cfg(feature = "lock-api")]
+#[test]
+fn check_lock_api() {
+    use lockapi;
+    use lock_api::{RawMutex, RawRwLock, RwLockUpgradableReadGuard, RwLockWriteGuard};
+
+    fn bump<R: RawMutex>(lock: &lock_api::Mutex<R, i32>) {
+        *lock.lock() += 1;
+    }
+
+    static GLOBAL: lockapi::Spinlock<i32> = lockapi::Spinlock::const_new(<lockapi::RawSpinlock as RawMutex>::INIT, 0);
+    enter(|scope| {
+        for _ in 0..4 {
+            scope.spawn(|| (0..1000).for_each(|_| bump(&GLOBAL)));
+        }
+    });
+    assert_eq!(*GLOBAL.lock(), 4000);
+    let guard = GLOBAL.lock();
+    assert!(GLOBAL.try_lock().is_none());
+    drop(guard);
+
+    fn read_sum<R: RawRwLock>(lock: &lock_api::RwLock<R, Vec<i32>>) -> i32 {
+        lock.read().iter().sum()
+    }
+
+    let rw = lockapi::SpinRWLock::new(vec![1]);
+    let upgradable = rw.upgradable_read();
+    assert_eq!(read_sum(&rw), 1);
+    assert!(rw.try_write().is_none());
+    let mut writer = RwLockUpgradableReadGuard::upgrade(upgradable);
+    writer.push(2);
+    let reader = RwLockWriteGuard::downgrade(writer);
+    assert!(rw.try_write().is_none());
+    assert_eq!(read_sum(&rw), 3);
+    drop(reader);
+    assert!(rw.try_write().is_some());
+}
+
+#[test]
+fn check_debug_state() {
+    use spinlock::SpinRWLock;
+    use event::Event;
+
+    let (promise, future) = Promise::<i32>::new();
+    assert_eq!(format!("{:?}", promise), "Promise { ready: false }");
+    assert_eq!(format!("{:?}", future), "Future { ready: false }");
+    promise.set(1);
+    let shared = future.share();
+    assert_eq!(format!("{:?}", shared), "SharedFuture { ready: true }");
+
+    let lock = Spinlock::new(5);
+    assert_eq!(format!("{:?}", lock), "Spinlock { data: 5, shared: false }");
+    let guard = lock.lock().unwrap();
+    assert_eq!(format!("{:?}", lock), "Spinlock { data: <locked>, shared: false }");
+    drop(guard);
+    lock.share();
+    assert_eq!(format!("{:?}", lock), "Spinlock { data: 5, shared: true }");
+
+    let rw = SpinRWLock::new(vec![1]);
+    let reader = rw.read().unwrap();
+    assert_eq!(format!("{:?}", rw), "SpinRWLock { data: [1], readers: 1, write_locked: false, poisoned: false }");
+    drop(reader);
+    let writer = rw.write().unwrap();
+    assert_eq!(format!("{:?}", rw), "SpinRWLock { data: <locked>, readers: 0, write_locked: true, poisoned: false }");
+    drop(writer);
+    assert_eq!(rw.reader_count(), 0);
+
+    let atom = Atom::new(3);
+    atom.store_val(4);
+    assert_eq!(format!("{:?}", atom), "Atom { value: Some(4) }");
+
+    let event = Event::new();
+    event.signal();
+    assert_eq!(format!("{:?}", event), "Event { set: true, generation: 1 }");
+}
+
+#[test]
+fn check_rwlock_poisoning() {
+    use spinlock::{SpinRWLock, TryLockError};
+
+    let lock = Arc::new(SpinRWLock::new(vec![1]));
+    let reader = lock.read().unwrap();
+    drop(reader);
+    // a panicking reader leaves nothing half done
+    let read_panic = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+        let _reader = lock.read().unwrap();
+        panic!("reader");
+    }));
+    assert!(read_panic.is_err());
+    assert!(!lock.is_poisoned());
+
+    let writer = {
+        let lock = lock.clone();
+        thread::spawn(move || {
+            let mut guard = lock.write_owned().unwrap();
+            guard.push(2);
+            panic!("half way through");
+        })
+    };
+    assert!(writer.join().is_err());
+    assert!(lock.is_poisoned());
+    assert_eq!(*lock.read().err().unwrap().into_inner(), vec![1, 2]);
+    match lock.try_write() {
+        Err(TryLockError::Poisoned(mut error)) => error.get_mut().push(3),
+        _ => panic!("poisoning not reported")
+    }
+    assert!(lock.upgradable_read().is_err());
+    lock.clear_poison();
+    assert_eq!(*lock.read().unwrap(), vec![1, 2, 3]);
+}
+
+#[test]
+fn check_atom_handoff() {
+    let slot = Atom::empty();
+    assert!(slot.is_empty());
+    assert!(slot.take().is_none());
+    assert!(slot.replace(1).is_none());
+    assert_eq!(*slot.load(), 1);
+    let previous = slot.replace(2).unwrap();
+    assert_eq!(Arc::try_unwrap(previous).ok(), Some(1));
+    assert_eq!(Arc::try_unwrap(slot.take().unwrap()).ok(), Some(2));
+    assert!(slot.try_load().is_none());
+
+    let produced = 1000;
+    let consumed = AtomicI64::new(0);
+    enter(|scope| {
+        let (slot, consumed) = (&slot, &consumed);
+        scope.spawn(move || (1..=produced).for_each(|val| {slot.replace(val);}));
+        scope.spawn(move || {
+            let mut last = 0;
+            while last != produced {
+                if let Some(val) = slot.take() {
+                    assert!(*val > last);
+                    last = *val;
+                    consumed.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        });
+    });
+    assert!(consumed.load(Ordering::Relaxed) > 0);
+    assert!(slot.is_empty());
+}
+
+#[test]
+fn check_atom_load_ref() {
+    let config = Arc::new(Atom::new(vec![1, 2]));
+    let pinned = config.load_ref();
+    assert_eq!(pinned.len(), 2);
+    let loaded = config.load();
+    assert_eq!(Arc::strong_count(&loaded), 2);
+
+    let stored = {
+        let config = config.clone();
+        spawn_async(move || config.store_val(vec![3]))
+    };
+    assert!(!stored.wait_timeout(time::Duration::from_millis(10)));
+    assert_eq!(*pinned, vec![1, 2]);
+    drop(pinned);
+    stored.take();
+    assert_eq!(*config.load_ref(), vec![3]);
+    assert!(Atom::<i32>::empty().try_load_ref().is_none());
+}
+
+#[test]
+fn check_atom_versions() {
+    let atom = Atom::new(0);
+    let (value, version) = atom.load_versioned();
+    assert_eq!((*value, version), (0, atom.version()));
+    atom.store_val(1);
+    assert!(!atom.store_if_version(version, Arc::new(2)));
+    assert_eq!(*atom.load(), 1);
+    let (_, version) = atom.load_versioned();
+    assert!(atom.store_if_version(version, Arc::new(2)));
+    assert!(atom.version() > version);
+
+    enter(|scope| {
+        for _ in 0..4 {
+            let atom = &atom;
+            scope.spawn(move || (0..250).for_each(|_| loop {
+                let (value, version) = atom.load_versioned();
+                if atom.store_if_version(version, Arc::new(*value + 1)) {
+                    break;
+                }
+            }));
+        }
+    });
+    assert_eq!(*atom.load(), 1002);
+}
+
+#[test]
+fn check_atom_history() {
+    assert!(Atom::new(0).history().is_empty());
+
+    let atom = Atom::with_history(0, 3);
+    for val in 1..5 {
+        atom.store_val(val);
+    }
+    atom.take();
+    let history = atom.history();
+    assert_eq!(history.iter().map(|entry| *entry.value).collect::<Vec<_>>(), vec![2, 3, 4]);
+    assert!(history.windows(2).all(|pair| pair[0].version < pair[1].version && pair[0].stored_at <= pair[1].stored_at));
+
+    atom.store_val(5);
+    let (_, version) = atom.load_versioned();
+    assert_eq!(atom.history().last().map(|entry| (*entry.value, entry.version)), Some((5, version)));
+}
+
+#[test]
+fn check_atom_cache() {
+    let atom = Atom::empty();
+    let mut cache = atom.cache();
+    assert!(cache.try_load().is_none());
+    atom.store_val(1);
+    let first = cache.load().clone();
+    assert_eq!(*first, 1);
+    assert!(Arc::ptr_eq(&first, cache.load()));
+    atom.store_val(2);
+    assert_eq!(**cache.load(), 2);
+    atom.take();
+    assert!(cache.try_load().is_none());
+
+    atom.store_val(0);
+    enter(|scope| {
+        let atom = &atom;
+        scope.spawn(move || (1..=1000).for_each(|val| atom.store_val(val)));
+        for _ in 0..4 {
+            let mut cache = atom.cache();
+            scope.spawn(move || {
+                let mut last = 0;
+                while last < 1000 {
+                    let val = **cache.load();
+                    assert!(val >= last);
+                    last = val;
+                }
+            });
+        }
+    });
+}
+
+#[test]
+fn check_atom_modify() {
+    let atom = Atom::new(vec![0]);
+    assert_eq!(*atom.modify(|val| val.push(1)), vec![0, 1]);
+    assert_eq!(*atom.load(), vec![0, 1]);
+
+    let counter = Atom::new(0);
+    enter(|scope| {
+        for _ in 0..4 {
+            let counter = &counter;
+            scope.spawn(move || (0..250).for_each(|_| {counter.modify(|val| *val += 1);}));
+        }
+    });
+    assert_eq!(*counter.load(), 1000);
+}
+
+#[test]
+fn check_sharded_counter() {
+    let counter = ShardedCounter::with_shards(3);
+    counter.add(5);
+    counter.decrement();
+    assert_eq!(counter.sum(), 4);
+    assert_eq!(format!("{:?}", counter), "ShardedCounter { sum: 4 }");
+
+    let counter = ShardedCounter::new();
+    enter(|scope| {
+        for _ in 0..8 {
+            let counter = &counter;
+            scope.spawn(move || (0..1000).for_each(|_| counter.increment()));
+        }
+    });
+    assert_eq!(counter.take(), 8000);
+    assert_eq!(counter.sum(), 0);
+}
+
+#[test]
+fn check_striped_lock() {
+    let locks = StripedLock::new(4);
+    let (first, mut second) = (0, 1);
+    while locks.stripe_of(&first) == locks.stripe_of(&second) {
+        second += 1;
+    }
+    let guard = locks.lock(&first);
+    // a different stripe is free, a key on the held one has to wait
+    drop(locks.lock(&second));
+    enter(|scope| {
+        let locks = &locks;
+        scope.spawn(move || assert!(locks.try_lock_for(&first, time::Duration::from_millis(10)).is_none()));
+    });
+    drop(guard);
+
+    // load and store separately, only the stripe keeps the increments from racing
+    let counts: Vec<_> = (0..4).map(|_| AtomicI64::new(0)).collect();
+    enter(|scope| {
+        for _ in 0..4 {
+            let (locks, counts) = (&locks, &counts);
+            scope.spawn(move || for i in 0..1000 {
+                let key = i % 4;
+                let _guard = locks.lock(&key);
+                counts[key].store(counts[key].load(Ordering::Relaxed) + 1, Ordering::Relaxed);
+            });
+        }
+    });
+    assert!(counts.iter().all(|count| count.load(Ordering::Relaxed) == 1000));
+}
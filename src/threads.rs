@@ -0,0 +1,96 @@
+use std::io;
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread::{self, JoinHandle};
+use future::{Executor, Job};
+
+// clones share the counter, so every thread spawned from one config gets a distinct name
+#[derive(Clone, Default, Debug)]
+pub struct ThreadConfig {
+    name_prefix: Option<Arc<str>>,
+    affinity: Option<Arc<[usize]>>,
+    spawned: Arc<AtomicUsize>
+}
+
+impl ThreadConfig {
+    pub fn new() -> ThreadConfig {
+        ThreadConfig::default()
+    }
+
+    // threads are named "<prefix>-<n>"
+    pub fn name_prefix(mut self, prefix: &str) -> ThreadConfig {
+        self.name_prefix = Some(Arc::from(prefix));
+        self
+    }
+
+    // the n-th spawned thread is pinned to cores[n % cores.len()]; pinning is best
+    // effort and only implemented on linux
+    pub fn affinity(mut self, cores: &[usize]) -> ThreadConfig {
+        assert!(!cores.is_empty(), "affinity needs at least one core");
+        self.affinity = Some(Arc::from(cores));
+        self
+    }
+
+    pub(crate) fn spawn<Func, R>(&self, f: Func) -> io::Result<JoinHandle<R>>
+        where Func: 'static + Send + FnOnce() -> R,
+              R: 'static + Send
+    {
+        unsafe { self.spawn_unchecked(f) }
+    }
+
+    // the caller must join the thread before anything f borrows goes away
+    pub(crate) unsafe fn spawn_unchecked<'a, Func, R>(&self, f: Func) -> io::Result<JoinHandle<R>>
+        where Func: 'a + Send + FnOnce() -> R,
+              R: 'a + Send
+    {
+        let n = self.spawned.fetch_add(1, Ordering::Relaxed);
+        let mut builder = thread::Builder::new();
+        if let Some(ref prefix) = self.name_prefix {
+            builder = builder.name(format!("{}-{}", prefix, n));
+        }
+        let core = self.affinity.as_ref().map(|cores| cores[n % cores.len()]);
+        builder.spawn_unchecked(move || {
+            if let Some(core) = core {
+                pin_current(core);
+            }
+            f()
+        })
+    }
+}
+
+// every job gets a fresh thread
+impl Executor<'static> for ThreadConfig {
+    fn execute(&self, job: Job<'static>) {
+        self.spawn(job).expect("failed to spawn thread");
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn pin_current(core: usize) {
+    extern "C" {
+        fn sched_setaffinity(pid: i32, size: usize, mask: *const u64) -> i32;
+    }
+    // matches glibc's fixed size cpu_set_t
+    let mut mask = [0u64; 16];
+    if core < mask.len() * 64 {
+        mask[core / 64] |= 1 << (core % 64);
+        unsafe {
+            sched_setaffinity(0, mem::size_of_val(&mask), mask.as_ptr());
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn pin_current(_core: usize) {}
+
+static DEFAULT: Mutex<Option<ThreadConfig>> = Mutex::new(None);
+
+// used by spawn_async() and by scopes entered without a config of their own
+pub fn set_default_config(config: ThreadConfig) {
+    *DEFAULT.lock().unwrap() = Some(config);
+}
+
+pub fn default_config() -> ThreadConfig {
+    DEFAULT.lock().unwrap().clone().unwrap_or_default()
+}